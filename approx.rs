@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use float_ord::FloatOrd;
+use crate::board_index::BoardIndex;
+use crate::cancel::CancelToken;
+use crate::csp_constraints::find_possible_boards;
+use crate::parsing::read_game;
+use crate::gui::SearchMode;
+use crate::math::count_assigned_packed;
+use crate::packed::{get_from_packed_state, set_in_packed_state};
+use crate::search::{terminal_value, is_best_square, candidate_squares, resign_value, optimistic_value, SearchResult};
+use crate::symmetry::Symmetries;
+
+// the clock is only read every that many nodes
+const NODES_PER_CLOCK_CHECK: usize = 1024;
+
+
+// how much a budgeted search may spend before it has to answer
+#[derive(Copy, Clone, Debug)]
+pub enum Budget
+{
+    Time(Duration),
+    Nodes(usize),
+}
+
+// Best move of a budgeted search. Whatever the rest of the game tree looks like,
+// the move is worth at least value and the best move at most upper_bound
+#[derive(Copy, Clone, Debug)]
+pub struct BudgetedResult
+{
+    pub square: (usize, usize),
+    pub value: f64,
+    pub upper_bound: f64,
+    pub depth: usize, // flips looked ahead by the last search that finished in time
+    pub nodes: usize,
+    pub seconds: f64,
+}
+
+impl BudgetedResult
+{
+    // how much worse than the optimal move the recommended one can be at most
+    pub fn max_loss(&self) -> f64
+    {
+        self.upper_bound - self.value
+    }
+}
+
+
+// Iterative deepening: searches 1, 2, 3... flips ahead until the budget runs out
+// and answers with the last depth that finished. Below that depth states are only bounded
+// (nothing gained / the best that can be gained, as upper_bound_ev in sh_square),
+// so the value of every square is an interval that gets narrower with each depth.
+// Depth 1 is always finished, Err(InconsistentPuzzle) or Err(TerminalState) if there's nothing to search
+pub fn compute_win_chance_budgeted(
+    org_packed_state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    mode: SearchMode,
+    budget: Budget,
+    cancel: &CancelToken,
+) -> Result<BudgetedResult, SearchResult>
+{
+    let start = Instant::now();
+
+    let (o_pb, o_count, weights) = find_possible_boards(org_packed_state, &sr, &sc, &br, &bc, level);
+
+    if o_pb.is_empty()
+    {
+        return Err(SearchResult::InconsistentPuzzle);
+    }
+
+    let board_index = BoardIndex::new(&o_pb, &o_count, &weights);
+    let mut board_sets = board_index.sets_by_depth();
+
+    if board_index.is_won(org_packed_state, &board_sets[0])
+    {
+        return Err(SearchResult::TerminalState);
+    }
+
+    let symmetries = Symmetries::new(&sr, &sc, &br, &bc);
+    let face_down = 25 - count_assigned_packed(org_packed_state);
+
+    let mut spent = Spent::new(budget, start, cancel);
+    spent.must_finish = true;
+
+    // below max_depth only what's certain: nothing gained, or the best that can be gained
+    let leaf = |state: u64, boards: &[u64]| (resign_value(state, mode), optimistic_value(&board_index, boards, mode));
+
+    let mut best = None;
+
+    for max_depth in 1..=face_down
+    {
+        let mut cache = HashMap::new();
+
+        let squares = match sh_bounded_root(
+            org_packed_state, max_depth,
+            &board_index, &mut board_sets,
+            &sr, &sc, &br, &bc, level,
+            &symmetries, &mut cache, &mut spent, &leaf, mode,
+        ) {
+            Some(squares) => squares,
+            None => break, // out of budget, the last depth has to do
+        };
+
+        // the pessimistic choice, so the guarantee holds
+        let &(square, value, _) = squares
+            .iter()
+            .max_by_key(|&&(_, lower, upper)| (FloatOrd(lower), FloatOrd(upper)))
+            .expect("A state that isn't won has a square to flip");
+
+        let upper_bound = squares
+            .iter()
+            .map(|&(_, _, upper)| upper)
+            .fold(resign_value(org_packed_state, mode), f64::max);
+
+        info!("Thread: Depth {} done after {} nodes, best square {:?} worth between {} and {}",
+              max_depth, spent.nodes, square, value, upper_bound);
+
+        best = Some(BudgetedResult {
+            square,
+            value,
+            upper_bound,
+            depth: max_depth,
+            nodes: spent.nodes,
+            seconds: start.elapsed().as_secs_f64(),
+        });
+
+        // nothing left to learn
+        if upper_bound - value < 1e-12
+        {
+            break;
+        }
+
+        // depth 1 has been done no matter what, from now on the budget counts
+        spent.must_finish = false;
+    }
+
+    Ok(best.expect("Depth 1 always finishes"))
+}
+
+
+// Only the budgeted search, on the position after the flips of a game file (see read_game),
+// for when the exact search takes too long. Prints the square to flip and how much it can lose at most
+pub fn budgeted_report(path: &str, mode: SearchMode, budget: Budget)
+{
+    let ((_, sr, sc, br, bc, level, board), flips) = read_game(path);
+
+    let mut state = 0;
+    for &(row, col) in &flips
+    {
+        let symbol = get_from_packed_state(board, row, col);
+        if symbol == 0
+        {
+            panic!("({}, {}) is a Voltorb, the game is over", row, col);
+        }
+
+        state = set_in_packed_state(state, row, col, symbol);
+    }
+
+    let cancel = CancelToken::new();
+
+    match compute_win_chance_budgeted(state, &sr, &sc, &br, &bc, level, mode, budget, &cancel)
+    {
+        Ok(r) => {
+            println!("{:?} after {} flips: flip {:?}, worth {:.4} to {:.4} (loses at most {:.4})",
+                     mode, flips.len(), r.square, r.value, r.upper_bound, r.max_loss());
+            println!("Looked {} flips ahead, {} nodes in {:.2}s", r.depth, r.nodes, r.seconds);
+        }
+        Err(result) => println!("{:?} after {} flips: nothing to search ({:?})", mode, flips.len(), result),
+    }
+}
+
+
+// what has been used up of the budget
+pub struct Spent<'a>
+{
+    pub nodes: usize,
+    budget: Budget,
+    start: Instant,
+    cancel: &'a CancelToken,
+    must_finish: bool, // neither the budget nor the cancel token stop the search
+}
+
+impl<'a> Spent<'a>
+{
+    // a time budget counts from start
+    pub fn new(budget: Budget, start: Instant, cancel: &'a CancelToken) -> Spent<'a>
+    {
+        Spent {
+            nodes: 0,
+            budget,
+            start,
+            cancel,
+            must_finish: false,
+        }
+    }
+
+    // only stops when cancelled
+    pub fn unbudgeted(cancel: &'a CancelToken) -> Spent<'a>
+    {
+        Spent::new(Budget::Nodes(usize::MAX), Instant::now(), cancel)
+    }
+
+    // counts a node, true if the search has to stop
+    fn exhausted(&mut self) -> bool
+    {
+        self.nodes += 1;
+
+        if self.must_finish
+        {
+            return false;
+        }
+
+        match self.budget
+        {
+            Budget::Nodes(n) => {
+                if self.nodes > n
+                {
+                    return true;
+                }
+            }
+
+            Budget::Time(d) => {
+                if self.nodes % NODES_PER_CLOCK_CHECK == 0 && self.start.elapsed() > d
+                {
+                    return true;
+                }
+            }
+        }
+
+        self.cancel.is_cancelled()
+    }
+}
+
+
+// (square, lower bound, upper bound) of every square worth searching, None if out of budget
+fn sh_bounded_root<L>(
+    state: u64,
+    max_depth: usize,
+    board_index: &BoardIndex,
+    board_sets: &mut Vec<Vec<u64>>,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    symmetries: &Symmetries,
+    cache: &mut HashMap<u64, (f64, f64)>,
+    spent: &mut Spent,
+    leaf: &L,
+    mode: SearchMode,
+) -> Option<Vec<((usize, usize), f64, f64)>>
+    where L: Fn(u64, &[u64]) -> (f64, f64)
+{
+    let acc = board_index.accumulate(&board_sets[0]);
+    let optimistic = optimistic_value(board_index, &board_sets[0], mode);
+
+    let mut candidates = Vec::with_capacity(25);
+    candidate_squares(state, &acc, &sr, &sc, &br, &bc, symmetries, mode, &mut candidates);
+
+    let mut squares = Vec::with_capacity(25);
+    for (row, col) in candidates
+    {
+        // every square gets its bounds, so nothing is cut
+        let (lower, upper) = sh_bounded_square(
+            0, max_depth, state, row, col, &acc, optimistic, f64::NEG_INFINITY,
+            board_index, board_sets,
+            &sr, &sc, &br, &bc, level,
+            symmetries, cache, spent, leaf, mode,
+        )?;
+
+        squares.push(((row, col), lower, upper));
+    }
+
+    Some(squares)
+}
+
+
+// The expansion of the approximate searches (budgeted and depth-limited): like sh_exact, but
+// max_depth flips below the root leaf(state, boards) gives bounds instead of searching deeper.
+// Returns (lower, upper) bounds on the value, equal if leaf returns equal ones (as a heuristic does).
+// None if out of budget. sh_exact keeps its own loop for the shared cache, threads and statistics
+pub fn sh_bounded<L>(
+    depth: usize,
+    max_depth: usize,
+    state: u64,
+    revealed: (usize, usize, usize), // (row, col, symbol) the parent revealed to get here
+    board_index: &BoardIndex,
+    board_sets: &mut Vec<Vec<u64>>,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    symmetries: &Symmetries,
+    cache: &mut HashMap<u64, (f64, f64)>,
+    spent: &mut Spent,
+    leaf: &L,
+    mode: SearchMode,
+) -> Option<(f64, f64)>
+    where L: Fn(u64, &[u64]) -> (f64, f64)
+{
+    if spent.exhausted()
+    {
+        return None;
+    }
+
+    // the depth of a state is the same everywhere in one search
+    let key = symmetries.canonical(state);
+    if let Some(&bounds) = cache.get(&key)
+    {
+        return Some(bounds);
+    }
+
+    let (row, col, symbol) = revealed;
+    let (parents, children) = board_sets.split_at_mut(depth);
+    board_index.restrict(&parents[depth - 1], row, col, symbol, &mut children[0]);
+    let boards = &board_sets[depth];
+
+    if let Some(v) = terminal_value(state, || board_index.is_won(state, boards), level, mode)
+    {
+        return Some((v, v));
+    }
+
+    if depth == max_depth
+    {
+        return Some(leaf(state, boards));
+    }
+
+    let acc = board_index.accumulate(boards);
+    let optimistic = optimistic_value(board_index, boards, mode);
+
+    let mut squares = Vec::with_capacity(25);
+    candidate_squares(state, &acc, &sr, &sc, &br, &bc, symmetries, mode, &mut squares);
+
+    let mut lower = resign_value(state, mode);
+    let mut upper = lower;
+
+    for (row, col) in squares
+    {
+        let (square_lower, square_upper) = sh_bounded_square(
+            depth, max_depth, state, row, col, &acc, optimistic, lower,
+            board_index, board_sets,
+            &sr, &sc, &br, &bc, level,
+            symmetries, cache, spent, leaf, mode,
+        )?;
+
+        lower = lower.max(square_lower);
+
+        // free information, no other square can be better (see sh_exact)
+        if is_best_square(&acc, row, col, mode)
+        {
+            upper = square_upper.max(lower);
+            break;
+        }
+
+        upper = upper.max(square_upper);
+    }
+
+    cache.insert(key, (lower, upper));
+    Some((lower, upper))
+}
+
+
+// Bounds on the expected value of revealing (row, col). Stops once its upper bound can't get above
+// best_lower, then it's worth at most best_lower and what it returns is below that too
+fn sh_bounded_square<L>(
+    depth: usize,
+    max_depth: usize,
+    state: u64,
+    row: usize,
+    col: usize,
+    acc: &[[[f64;4];5];5],
+    optimistic: f64, // no symbol is worth more
+    best_lower: f64,
+    board_index: &BoardIndex,
+    board_sets: &mut Vec<Vec<u64>>,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    symmetries: &Symmetries,
+    cache: &mut HashMap<u64, (f64, f64)>,
+    spent: &mut Spent,
+    leaf: &L,
+    mode: SearchMode,
+) -> Option<(f64, f64)>
+    where L: Fn(u64, &[u64]) -> (f64, f64)
+{
+    let mut lower = 0.0;
+    let mut upper = 0.0;
+    let mut upper_bound_ev = (acc[row][col][1] + acc[row][col][2] + acc[row][col][3]) * optimistic;
+
+    for symbol in 1..4
+    {
+        if acc[row][col][symbol] == 0.0
+        {
+            continue;
+        }
+
+        // cut if it can't be better than current best
+        if upper + upper_bound_ev <= best_lower
+        {
+            break;
+        }
+
+        let (l, u) = sh_bounded(
+            depth + 1, max_depth,
+            set_in_packed_state(state, row, col, symbol),
+            (row, col, symbol),
+            board_index, board_sets,
+            &sr, &sc, &br, &bc, level,
+            symmetries, cache, spent, leaf, mode,
+        )?;
+
+        lower += l * acc[row][col][symbol];
+        upper += u * acc[row][col][symbol];
+        upper_bound_ev -= acc[row][col][symbol] * optimistic;
+    }
+
+    Some((lower, upper))
+}
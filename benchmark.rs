@@ -1,10 +1,20 @@
 use std::time::{Instant, Duration};
 use crate::search::{compute_win_chance_exact, SearchResult};
-use dashmap::DashMap;
+use crate::cancel::CancelToken;
+use crate::approx::{compute_win_chance_budgeted, Budget};
+use crate::depth_limited::{compute_win_chance_depth_limited, Heuristic};
+use crate::sampling::{compute_win_chance_sampled, SampledSearch};
+use crate::stats::{format_statistics, USE_DEPTH_STATISTICS};
+use crate::transposition::{TranspositionTable, ReplacementPolicy, ValuePrecision};
 use crossbeam_channel::{unbounded, RecvError};
 use crate::gui::{ReportMessage, SearchMode};
-use std::thread::sleep;
-use crate::parsing::examples_357;
+use std::thread::{sleep, spawn};
+use crate::parsing::{examples_357, hardest_5};
+use dashmap::DashMap;
+use rayon::prelude::*;
+use std::cmp::{max, min};
+use std::mem::size_of;
+use crate::csp_constraints::{find_possible_boards, find_possible_boards_square_by_square};
 
 // benchmarks the algorithm on the 348 hardest puzzles using one core
 // might take several minutes
@@ -22,14 +32,14 @@ pub fn benchmark()
     let mode = SearchMode::WinEight;
 
     // preparing cache
-    let cache = DashMap::with_capacity(111_744_155);
+    let cache = TranspositionTable::new(4096, ReplacementPolicy::TwoTier, ValuePrecision::F64);
 
     // Benchmarking
     let mut total_time = 0.0;
 
     for (nr, sr, sc, br, bc, level, _state) in puzzles
     {
-        let (control_sender, control_receiver) = unbounded();
+        let cancel = CancelToken::new();
         let (result_reporting_sender, result_reporting_receiver) = unbounded();
 
         cache.clear();
@@ -37,9 +47,9 @@ pub fn benchmark()
         let search_result = compute_win_chance_exact(
             0, &sr, &sc, &br, &bc, level,
             &cache,
-            &control_receiver,
+            &cancel,
             &result_reporting_sender,
-            &control_sender, mode, threads);
+            mode, threads);
 
         if let SearchResult::SuccessfulSearchWithInfo(prob, time, nodes) = search_result
         {
@@ -55,4 +65,535 @@ pub fn benchmark()
     loop {
         sleep(Duration::from_secs(3600));
     }
-}
\ No newline at end of file
+}
+
+// compares the transposition table with a DashMap on the hardest puzzles:
+// solves each puzzle once to get the states of a real search,
+// then replays them on every table with all cores (inserting everything, then looking everything up)
+pub fn benchmark_cache()
+{
+    println!("Benchmarking the cache! Might take minutes...");
+
+    let threads = num_cpus::get();
+    let mode = SearchMode::WinChance;
+
+    for (nr, sr, sc, br, bc, level, _state) in hardest_5()
+    {
+        let cancel = CancelToken::new();
+        let (result_reporting_sender, _result_reporting_receiver) = unbounded();
+
+        let cache = TranspositionTable::new(4096, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+        let search_result = compute_win_chance_exact(
+            0, &sr, &sc, &br, &bc, level,
+            &cache,
+            &cancel,
+            &result_reporting_sender,
+            mode, threads);
+
+        if let SearchResult::SuccessfulSearchWithInfo(_, time, nodes) = search_result
+        {
+            println!("Puzzle {}: {} nodes in {:.2}s, {:.0} nodes/s", nr, nodes, time, nodes as f64 / time);
+        }
+
+        let mut entries = Vec::with_capacity(cache.len());
+        cache.for_each(|state, value| entries.push((state, value)));
+        drop(cache);
+
+        // hashbrown: key, value and a control byte per entry
+        let map = DashMap::with_capacity(entries.len());
+        let map_memory = map.capacity() * (size_of::<(u64, f64)>() + 1);
+
+        let start = Instant::now();
+        entries.par_iter().for_each(|&(state, value)| { map.insert(state, value); });
+        let insert_time = start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        let found = entries.par_iter().filter(|&&(state, _)| map.get(&state).is_some()).count();
+        let get_time = start.elapsed().as_secs_f64();
+
+        println!("    DashMap: ~{} MB, {:.0} inserts/s, {:.0} gets/s, found {}/{}",
+                 map_memory >> 20, entries.len() as f64 / insert_time,
+                 entries.len() as f64 / get_time, found, entries.len());
+        drop(map);
+
+        for &precision in [ValuePrecision::F64, ValuePrecision::F32].iter()
+        {
+            // room for every entry even after rounding down to a power of two,
+            // so the replay measures speed and not evictions
+            let bytes_per_entry = if precision == ValuePrecision::F64 { 16 } else { 8 };
+            let megabytes = max(1, (4 * entries.len() * bytes_per_entry) >> 20);
+            let table = TranspositionTable::new(megabytes, ReplacementPolicy::TwoTier, precision);
+
+            let start = Instant::now();
+            entries.par_iter().for_each(|&(state, value)| table.insert(state, value));
+            let insert_time = start.elapsed().as_secs_f64();
+
+            let start = Instant::now();
+            let found = entries.par_iter().filter(|&&(state, _)| table.get(&state).is_some()).count();
+            let get_time = start.elapsed().as_secs_f64();
+
+            let max_error = entries
+                .iter()
+                .filter_map(|&(state, value)| table.get(&state).map(|v| (v - value).abs()))
+                .fold(0.0, f64::max);
+
+            println!("    {:?} table: {} MB, {:.0} inserts/s, {:.0} gets/s, found {}/{}, max error {:e}",
+                     precision, table.memory_bytes() >> 20, entries.len() as f64 / insert_time,
+                     entries.len() as f64 / get_time, found, entries.len(), max_error);
+        }
+    }
+}
+
+
+// solves the hardest puzzles and prints how the bounds on the value close in during the search,
+// the last line before the result is what stopping early would have guaranteed
+pub fn benchmark_bounds()
+{
+    println!("Printing bounds while solving the hardest puzzles...");
+
+    let threads = num_cpus::get();
+    let mode = SearchMode::WinEight;
+
+    for (nr, sr, sc, br, bc, level, _state) in hardest_5()
+    {
+        let (result_reporting_sender, result_reporting_receiver) = unbounded();
+
+        let start = Instant::now();
+        let search = spawn(move || {
+            let cancel = CancelToken::new();
+            let cache = TranspositionTable::new(4096, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+            compute_win_chance_exact(
+                0, &sr, &sc, &br, &bc, level,
+                &cache,
+                &cancel,
+                &result_reporting_sender,
+                mode, threads)
+        });
+
+        // ends when the search is done and drops its sender
+        for msg in result_reporting_receiver.iter()
+        {
+            if let ReportMessage::RootBounds(lower, upper, row, col) = msg
+            {
+                println!("Puzzle {} after {:.2}s: between {:.4} and {:.4}, best so far ({}, {})",
+                         nr, start.elapsed().as_secs_f64(), lower, upper, row, col);
+            }
+        }
+
+        if let SearchResult::SuccessfulSearchWithInfo(prob, time, nodes) = search.join().expect("Search thread panicked")
+        {
+            println!("Puzzle {}: {} in {:.2}s with {} nodes", nr, prob, time, nodes);
+        }
+    }
+}
+
+// budgeted search with a tenth of a second per puzzle against the exact search,
+// the exact value has to be within the guaranteed bounds. Panics if it isn't
+pub fn benchmark_budgeted()
+{
+    println!("Benchmarking the budgeted search...");
+
+    let threads = num_cpus::get();
+    let mode = SearchMode::WinChance;
+    let budget = Budget::Time(Duration::from_millis(100));
+
+    let cache = TranspositionTable::new(4096, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+    let mut total_loss = 0.0;
+    let mut puzzles = 0;
+
+    for (nr, sr, sc, br, bc, level, _state) in examples_357()
+    {
+        let cancel = CancelToken::new();
+        let (result_reporting_sender, _result_reporting_receiver) = unbounded();
+
+        cache.clear();
+
+        let exact = compute_win_chance_exact(
+            0, &sr, &sc, &br, &bc, level,
+            &cache,
+            &cancel,
+            &result_reporting_sender,
+            mode, threads);
+
+        let budgeted = compute_win_chance_budgeted(0, &sr, &sc, &br, &bc, level, mode, budget, &cancel);
+
+        if let (SearchResult::SuccessfulSearchWithInfo(prob, _, _), Ok(result)) = (exact, budgeted)
+        {
+            assert!(result.value <= prob + 1e-9 && prob <= result.upper_bound + 1e-9,
+                    "Puzzle {}: exact value {} outside of [{}, {}]", nr, prob, result.value, result.upper_bound);
+
+            println!("Puzzle {}: {:?} worth {:.4} to {:.4} (exact {:.4}) after {} flips, {} nodes in {:.2}s",
+                     nr, result.square, result.value, result.upper_bound, prob,
+                     result.depth, result.nodes, result.seconds);
+
+            total_loss += result.max_loss();
+            puzzles += 1;
+        }
+    }
+
+    println!("Average guaranteed loss: {:.4}", total_loss / puzzles as f64);
+}
+
+// how much worse than optimal the move of the depth-limited search is, for both heuristics and a few depths.
+// The loss is measured with the exact values of the squares
+pub fn benchmark_depth_limited()
+{
+    println!("Benchmarking the depth-limited search...");
+
+    let threads = num_cpus::get();
+    let mode = SearchMode::WinChance;
+    let heuristics = [Heuristic::SafeSquareProduct, Heuristic::NoTwoOrThreeLeft];
+    let max_flips = 4;
+
+    let cache = TranspositionTable::new(4096, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+    // heuristic, flips - 1 -> (total loss, total time)
+    let mut totals = [[(0.0, 0.0); 4]; 2];
+    let mut puzzles = 0;
+
+    for (nr, sr, sc, br, bc, level, _state) in examples_357()
+    {
+        let cancel = CancelToken::new();
+        let (result_reporting_sender, result_reporting_receiver) = unbounded();
+
+        cache.clear();
+
+        let exact = compute_win_chance_exact(
+            0, &sr, &sc, &br, &bc, level,
+            &cache,
+            &cancel,
+            &result_reporting_sender,
+            mode, threads);
+
+        let optimum = match exact
+        {
+            SearchResult::SuccessfulSearchWithInfo(prob, _, _) => prob,
+            _ => continue,
+        };
+
+        let mut exact_values = [[0.0;5];5];
+        for msg in result_reporting_receiver.try_iter()
+        {
+            if let ReportMessage::SquareWinProb(row, col, value) = msg
+            {
+                exact_values[row][col] = value;
+            }
+        }
+
+        for (h, &heuristic) in heuristics.iter().enumerate()
+        {
+            for flips in 1..=max_flips
+            {
+                let (result_reporting_sender, result_reporting_receiver) = unbounded();
+
+                let result = compute_win_chance_depth_limited(
+                    0, &sr, &sc, &br, &bc, level,
+                    &cancel,
+                    &result_reporting_sender,
+                    mode, threads, flips, heuristic);
+
+                if let SearchResult::SuccessfulSearchWithInfo(_, time, _) = result
+                {
+                    // the square the depth-limited search likes best
+                    let mut best = (f64::MIN, 0, 0);
+                    for msg in result_reporting_receiver.try_iter()
+                    {
+                        if let ReportMessage::SquareWinProb(row, col, value) = msg
+                        {
+                            if value > best.0
+                            {
+                                best = (value, row, col);
+                            }
+                        }
+                    }
+
+                    let loss = optimum - exact_values[best.1][best.2];
+                    totals[h][flips - 1].0 += loss;
+                    totals[h][flips - 1].1 += time;
+
+                    println!("Puzzle {}: {:?} with {} flips picks ({}, {}), loses {:.4}",
+                             nr, heuristic, flips, best.1, best.2, loss);
+                }
+            }
+        }
+
+        puzzles += 1;
+    }
+
+    for (h, &heuristic) in heuristics.iter().enumerate()
+    {
+        for flips in 1..=max_flips
+        {
+            let (loss, time) = totals[h][flips - 1];
+            println!("{:?} with {} flips: average loss {:.4}, {:.3}s per puzzle",
+                     heuristic, flips, loss / puzzles as f64, time / puzzles as f64);
+        }
+    }
+}
+
+// sampled search on the level 7 and 8 puzzles, where there are the most boards.
+// The exact values of the squares tell how much the recommended square loses. Its time includes
+// drawing the boards
+pub fn benchmark_sampled()
+{
+    println!("Benchmarking the sampled search on levels 7 and 8...");
+
+    let threads = num_cpus::get();
+    let mode = SearchMode::WinChance;
+    let search = SampledSearch::Exact;
+    let (samples, runs) = (200, 10);
+    // cleared for every run, a big one would take longer to clear than to search
+    let sample_cache_mb = 256;
+
+    let cache = TranspositionTable::new(1024, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+    let mut total_loss = 0.0;
+    let mut total_time = 0.0;
+    let mut puzzles = 0;
+
+    for (nr, sr, sc, br, bc, level, _state) in examples_357()
+    {
+        if level < 7
+        {
+            continue;
+        }
+
+        let cancel = CancelToken::new();
+        let (result_reporting_sender, result_reporting_receiver) = unbounded();
+
+        cache.clear();
+
+        let optimum = match compute_win_chance_exact(
+            0, &sr, &sc, &br, &bc, level,
+            &cache,
+            &cancel,
+            &result_reporting_sender,
+            mode, threads)
+        {
+            SearchResult::SuccessfulSearchWithInfo(prob, _, _) => prob,
+            _ => continue,
+        };
+
+        let mut exact_values = [[0.0;5];5];
+        for msg in result_reporting_receiver.try_iter()
+        {
+            if let ReportMessage::SquareWinProb(row, col, value) = msg
+            {
+                exact_values[row][col] = value;
+            }
+        }
+
+        if let Ok(result) = compute_win_chance_sampled(
+            0, &sr, &sc, &br, &bc, level,
+            sample_cache_mb, &cancel, mode, threads,
+            search, samples, runs, nr as u64)
+        {
+            let (row, col) = result.square;
+            let estimate = result.estimates[row][col].expect("The recommended square has an estimate");
+            let loss = optimum - exact_values[row][col];
+
+            println!("Puzzle {} (level {}, {} boards): ({}, {}) worth {:.4} +- {:.4}, exactly {:.4} of {:.4} in {:.2}s",
+                     nr, level, result.boards, row, col, estimate.mean, estimate.half_width,
+                     exact_values[row][col], optimum, result.seconds);
+
+            total_loss += loss;
+            total_time += result.seconds;
+            puzzles += 1;
+        }
+    }
+
+    println!("{} puzzles: average loss {:.4}, {:.2}s per puzzle",
+             puzzles, total_loss / puzzles as f64, total_time / puzzles as f64);
+}
+
+// nodes and time of the exact search on examples.txt and hardest.txt,
+// for comparing pruning changes (run once with and once without them)
+pub fn benchmark_nodes()
+{
+    println!("Counting nodes of the exact search...");
+
+    let threads = num_cpus::get();
+    let mode = SearchMode::WinChance;
+
+    let cache = TranspositionTable::new(1024, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+    for (name, puzzles) in [("examples.txt", examples_357()), ("hardest.txt", hardest_5())]
+    {
+        let mut total_nodes = 0;
+        let mut total_time = 0.0;
+
+        for (_, sr, sc, br, bc, level, _state) in puzzles
+        {
+            let cancel = CancelToken::new();
+            let (result_reporting_sender, _result_reporting_receiver) = unbounded();
+
+            cache.clear();
+
+            if let SearchResult::SuccessfulSearchWithInfo(_, time, nodes) = compute_win_chance_exact(
+                0, &sr, &sc, &br, &bc, level,
+                &cache,
+                &cancel,
+                &result_reporting_sender,
+                mode, threads)
+            {
+                total_nodes += nodes;
+                total_time += time;
+            }
+        }
+
+        println!("{}: {} nodes in {:.2}s", name, total_nodes, total_time);
+    }
+}
+
+
+// wall time and CPU use of the exact search on hardest.txt with 1, 8 and 16 threads.
+// Utilization is the CPU time of the process over the time the threads could have used,
+// so with fewer cores than threads it can't get near 100%. Change PARALLEL_DEPTH in search.rs to compare
+pub fn benchmark_threads()
+{
+    println!("Timing the exact search on hardest.txt with {} cores...", num_cpus::get());
+
+    let mode = SearchMode::WinChance;
+
+    let cache = TranspositionTable::new(1024, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+    for threads in [1, 8, 16]
+    {
+        let start = Instant::now();
+        let cpu_start = process_cpu_seconds();
+
+        for (_, sr, sc, br, bc, level, _state) in hardest_5()
+        {
+            let cancel = CancelToken::new();
+            let (result_reporting_sender, _result_reporting_receiver) = unbounded();
+
+            cache.clear();
+
+            compute_win_chance_exact(
+                0, &sr, &sc, &br, &bc, level,
+                &cache,
+                &cancel,
+                &result_reporting_sender,
+                mode, threads);
+        }
+
+        let wall = start.elapsed().as_secs_f64();
+
+        match (cpu_start, process_cpu_seconds())
+        {
+            (Some(cpu_start), Some(cpu_end)) => {
+                let cpu = cpu_end - cpu_start;
+                let usable = min(threads, num_cpus::get()) as f64 * wall;
+                println!("{:>2} threads: {:.2}s, {:.2}s CPU, {:.0}% utilization",
+                         threads, wall, cpu, 100.0 * cpu / usable);
+            }
+            _ => println!("{:>2} threads: {:.2}s", threads, wall),
+        }
+    }
+}
+
+// user and system time of all threads of this process so far, None where there's no /proc (not Linux).
+// Assumes the usual 100 clock ticks per second
+fn process_cpu_seconds() -> Option<f64>
+{
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+
+    // the command name in parentheses may contain spaces, the fields after it don't
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 2..].split(' ').collect();
+    let user: u64 = fields.get(11)?.parse().ok()?;
+    let system: u64 = fields.get(12)?.parse().ok()?;
+
+    Some((user + system) as f64 / 100.0)
+}
+
+
+// solves the hardest puzzles and prints the counters of every depth of each search
+pub fn benchmark_statistics()
+{
+    println!("Printing search statistics of the hardest puzzles...");
+
+    if !USE_DEPTH_STATISTICS
+    {
+        println!("Turn on USE_DEPTH_STATISTICS in stats.rs first");
+        return;
+    }
+
+    let threads = num_cpus::get();
+    let mode = SearchMode::WinChance;
+
+    let cache = TranspositionTable::new(1024, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+    for (nr, sr, sc, br, bc, level, _state) in hardest_5()
+    {
+        let cancel = CancelToken::new();
+        let (result_reporting_sender, result_reporting_receiver) = unbounded();
+
+        cache.clear();
+
+        if let SearchResult::SuccessfulSearchWithInfo(prob, time, _) = compute_win_chance_exact(
+            0, &sr, &sc, &br, &bc, level,
+            &cache,
+            &cancel,
+            &result_reporting_sender,
+            mode, threads)
+        {
+            println!("Puzzle {}: {} in {:.2}s", nr, prob, time);
+        }
+
+        for msg in result_reporting_receiver.try_iter()
+        {
+            if let ReportMessage::Statistics(statistics) = msg
+            {
+                print!("{}", format_statistics(&statistics));
+            }
+        }
+    }
+}
+
+// times finding the possible boards of the root on levels 7 and 8, where it takes longest,
+// row by row against the old square by square enumeration. Panics if they don't agree
+pub fn benchmark_enumeration()
+{
+    println!("Benchmarking board enumeration on levels 7 and 8...");
+
+    let puzzles: Vec<_> = examples_357()
+        .into_iter()
+        .chain(hardest_5())
+        .filter(|&(_, _, _, _, _, level, _)| level >= 7)
+        .collect();
+
+    // level -> (puzzles, boards, time square by square, time row by row)
+    let mut totals = [(0, 0, 0.0, 0.0); 9];
+
+    for (nr, sr, sc, br, bc, level, _state) in puzzles
+    {
+        let start = Instant::now();
+        let by_squares = find_possible_boards_square_by_square(0, &sr, &sc, &br, &bc, level);
+        let squares_time = start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        let by_rows = find_possible_boards(0, &sr, &sc, &br, &bc, level);
+        let rows_time = start.elapsed().as_secs_f64();
+
+        assert_eq!(by_squares, by_rows, "Enumerations disagree on puzzle {}", nr);
+
+        println!("Puzzle {} (level {}): {} boards, {:.2}ms square by square, {:.2}ms row by row",
+                 nr, level, by_rows.0.len(), squares_time * 1000.0, rows_time * 1000.0);
+
+        let total = &mut totals[level];
+        total.0 += 1;
+        total.1 += by_rows.0.len();
+        total.2 += squares_time;
+        total.3 += rows_time;
+    }
+
+    for level in 7..9
+    {
+        let (count, boards, squares_time, rows_time) = totals[level];
+        println!("Level {}: {} puzzles, {} boards, {:.2}s square by square, {:.2}s row by row ({:.1}x)",
+                 level, count, boards, squares_time, rows_time, squares_time / rows_time);
+    }
+}
@@ -0,0 +1,329 @@
+use crate::packed::{get_from_packed_state, coins_of_state};
+
+// lower bit of every square
+const LOW_BITS: u64 = 0x0001_5555_5555_5555;
+
+
+// For each (square, symbol) the set of root boards having that symbol there, as bitsets.
+// A set of boards left at a node is a bitset over the root boards too,
+// revealing a square is a single AND and symbol weights are popcounts per weight group.
+// Pays off for puzzles with thousands of boards, where copying and rescanning boards dominated
+pub struct BoardIndex
+{
+    boards: Vec<u64>, // root boards sorted by weight group
+    coins: Vec<usize>, // coins of each root board
+    two_or_three: Vec<u64>, // of each root board, lower bit set on squares with a 2 or 3
+    words: usize, // u64 words per bitset
+    symbol_sets: Vec<u64>, // word w of the set of (square, symbol) is at w * 100 + square * 4 + symbol
+    groups: Vec<(usize, usize)>, // root boards of each weight group, [start, end)
+    weights: Vec<f64>,
+}
+
+impl BoardIndex
+{
+    // boards, counts and weights as returned by find_possible_boards
+    pub fn new(
+        o_pb: &Vec<u64>,
+        o_count: &Vec<usize>,
+        weights: &Vec<f64>,
+    ) -> BoardIndex
+    {
+        let words = o_pb.len().div_ceil(64);
+
+        let mut symbol_sets = vec![0; words * 100];
+        for (i, &board) in o_pb.iter().enumerate()
+        {
+            for square in 0..25
+            {
+                let symbol = get_from_packed_state(board, square / 5, square % 5);
+                symbol_sets[(i / 64) * 100 + square * 4 + symbol] |= 1 << (i % 64);
+            }
+        }
+
+        let mut groups = Vec::with_capacity(o_count.len());
+        let mut start = 0;
+        for &count in o_count
+        {
+            groups.push((start, start + count));
+            start += count;
+        }
+
+        BoardIndex {
+            boards: o_pb.clone(),
+            coins: o_pb.iter().map(|&board| coins_of_state(board)).collect(),
+            two_or_three: o_pb.iter().map(|&board| (board >> 1) & LOW_BITS).collect(),
+            words,
+            symbol_sets,
+            groups,
+            weights: weights.clone(),
+        }
+    }
+
+    // every root board
+    pub fn root_set(&self) -> Vec<u64>
+    {
+        let mut set = vec![u64::MAX; self.words];
+        if self.boards.len() % 64 != 0
+        {
+            set[self.words - 1] = (1 << (self.boards.len() % 64)) - 1;
+        }
+        set
+    }
+
+    // one bitset per depth, all of them preallocated
+    pub fn sets_by_depth(&self) -> Vec<Vec<u64>>
+    {
+        let mut sets = vec![vec![0; self.words]; 26];
+        sets[0] = self.root_set();
+        sets
+    }
+
+    // boards of the parent with symbol at (row, col)
+    pub fn restrict(
+        &self,
+        parent: &[u64],
+        row: usize,
+        col: usize,
+        symbol: usize,
+        child: &mut [u64],
+    )
+    {
+        let k = (row * 5 + col) * 4 + symbol;
+        for w in 0..self.words
+        {
+            child[w] = parent[w] & self.symbol_sets[w * 100 + k];
+        }
+    }
+
+    // same as accumulate_symbol_weights for the boards in set
+    pub fn accumulate(&self, set: &[u64]) -> [[[f64;4];5];5]
+    {
+        // square * 4 + symbol
+        let mut weights = [0.0; 100];
+
+        for (g, &(start, end)) in self.groups.iter().enumerate()
+        {
+            let weight = self.weights[g];
+
+            // boards of dense words are counted first, converted once per group
+            let mut counts = [0_u32; 100];
+            let mut any_dense = false;
+
+            for w in start / 64..end.div_ceil(64)
+            {
+                let word = set[w] & group_mask(start, end, w);
+                if word == 0
+                {
+                    continue;
+                }
+
+                // few boards left deep in the tree, reading them is cheaper than 100 popcounts
+                if word.count_ones() <= 8
+                {
+                    let mut bits = word;
+                    while bits != 0
+                    {
+                        let board = self.boards[w * 64 + bits.trailing_zeros() as usize];
+                        for square in 0..25
+                        {
+                            weights[square * 4 + get_from_packed_state(board, square / 5, square % 5)] += weight;
+                        }
+                        bits &= bits - 1;
+                    }
+                    continue;
+                }
+
+                let symbol_words = &self.symbol_sets[w * 100..(w + 1) * 100];
+                for k in 0..100
+                {
+                    counts[k] += (word & symbol_words[k]).count_ones();
+                }
+                any_dense = true;
+            }
+
+            if any_dense
+            {
+                for k in 0..100
+                {
+                    weights[k] += counts[k] as f64 * weight;
+                }
+            }
+        }
+
+        let total = weights[0] + weights[1] + weights[2] + weights[3];
+
+        let mut acc = [[[0.0;4];5];5];
+        for k in 0..100
+        {
+            acc[k / 20][(k / 4) % 5][k % 4] = weights[k] / total;
+        }
+
+        acc
+    }
+
+    // no board in set has a 2 or 3 on a face-down square
+    pub fn is_won(&self, state: u64, set: &[u64]) -> bool
+    {
+        let face_down = !(state | (state >> 1)) & LOW_BITS;
+
+        for (w, &word) in set.iter().enumerate()
+        {
+            let mut bits = word;
+            while bits != 0
+            {
+                if self.two_or_three[w * 64 + bits.trailing_zeros() as usize] & face_down != 0
+                {
+                    return false;
+                }
+                bits &= bits - 1;
+            }
+        }
+
+        true
+    }
+
+    // weighted share of the boards in set without a 2 or 3 on a face-down square
+    pub fn won_share(&self, state: u64, set: &[u64]) -> f64
+    {
+        let face_down = !(state | (state >> 1)) & LOW_BITS;
+
+        let mut won = 0.0;
+        let mut total = 0.0;
+
+        for (g, &(start, end)) in self.groups.iter().enumerate()
+        {
+            let weight = self.weights[g];
+
+            for w in start / 64..end.div_ceil(64)
+            {
+                let mut bits = set[w] & group_mask(start, end, w);
+                while bits != 0
+                {
+                    if self.two_or_three[w * 64 + bits.trailing_zeros() as usize] & face_down == 0
+                    {
+                        won += weight;
+                    }
+                    total += weight;
+                    bits &= bits - 1;
+                }
+            }
+        }
+
+        won / total
+    }
+
+    // in every board of set the symbol of square a follows from the symbol of square b,
+    // and a is a Voltorb wherever b is one (squares as row * 5 + col)
+    pub fn is_determined_by(&self, set: &[u64], a: usize, b: usize) -> bool
+    {
+        // symbol of a on the boards with symbol t at b, 4 while there's none yet
+        let mut image = [4; 4];
+
+        for (w, &word) in set.iter().enumerate()
+        {
+            if word == 0
+            {
+                continue;
+            }
+
+            for t in 0..4
+            {
+                let with_t = word & self.symbol_sets[w * 100 + b * 4 + t];
+                if with_t == 0
+                {
+                    continue;
+                }
+
+                for s in 0..4
+                {
+                    if with_t & self.symbol_sets[w * 100 + a * 4 + s] != 0
+                    {
+                        if image[t] != 4 && image[t] != s
+                        {
+                            return false;
+                        }
+                        image[t] = s;
+                    }
+                }
+            }
+        }
+
+        image[0] == 4 || image[0] == 0
+    }
+
+    pub fn max_coins(&self, set: &[u64]) -> usize
+    {
+        let mut max_coins = 0;
+        self.for_each_board(set, |i| max_coins = std::cmp::max(max_coins, self.coins[i]));
+        max_coins
+    }
+
+    // max_coins of the boards of set with each symbol at (row, col), in one pass over set
+    pub fn max_coins_by_symbol(&self, set: &[u64], row: usize, col: usize) -> [usize; 4]
+    {
+        let mut max_coins = [0; 4];
+        self.for_each_board(set, |i| {
+            let symbol = get_from_packed_state(self.boards[i], row, col);
+            max_coins[symbol] = std::cmp::max(max_coins[symbol], self.coins[i]);
+        });
+        max_coins
+    }
+
+    pub fn count(&self, set: &[u64]) -> usize
+    {
+        set.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    // state with every square revealed that has the same symbol in all boards of the set (except Voltorbs).
+    // It's a function of the set alone and has exactly the same boards,
+    // so states with the same boards left get the same one
+    pub fn board_set_state(&self, set: &[u64]) -> u64
+    {
+        let mut first = None;
+        let mut differing = 0;
+        self.for_each_board(set, |i| {
+            let board = self.boards[i];
+            match first
+            {
+                None => first = Some(board),
+                Some(f) => differing |= board ^ f,
+            }
+        });
+
+        let first = first.expect("Board set is empty");
+        let differing_squares = (differing & LOW_BITS) | ((differing >> 1) & LOW_BITS);
+
+        // Voltorbs are 0 in boards, the same as face-down squares in states
+        first & !(differing_squares | (differing_squares << 1))
+    }
+
+    // index of every root board in set
+    fn for_each_board<F: FnMut(usize)>(&self, set: &[u64], mut f: F)
+    {
+        for (w, &word) in set.iter().enumerate()
+        {
+            let mut bits = word;
+            while bits != 0
+            {
+                f(w * 64 + bits.trailing_zeros() as usize);
+                bits &= bits - 1;
+            }
+        }
+    }
+}
+
+
+// bits of word w that belong to the boards [start, end)
+fn group_mask(start: usize, end: usize, w: usize) -> u64
+{
+    let low = std::cmp::max(start, w * 64) - w * 64;
+    let high = std::cmp::min(end, w * 64 + 64) - w * 64;
+
+    if high - low == 64
+    {
+        u64::MAX
+    }
+    else {
+        ((1 << (high - low)) - 1) << low
+    }
+}
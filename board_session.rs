@@ -0,0 +1,107 @@
+use crate::csp_constraints::find_possible_boards;
+use crate::packed::is_possible_board_of_state;
+
+
+// Possible boards of the position the GUI is at.
+// Revealing squares only ever removes boards, so after a move they're filtered
+// from the boards found before instead of enumerating them again
+pub struct BoardSession
+{
+    base: Option<(u64, Vec<u64>, Vec<usize>, Vec<f64>)>, // last state enumerated from scratch, its boards, counts and weights
+}
+
+impl BoardSession
+{
+    pub fn new() -> BoardSession
+    {
+        BoardSession { base: None }
+    }
+
+    // the boards belong to other constraints from now on
+    pub fn clear(&mut self)
+    {
+        self.base = None;
+    }
+
+    // same as find_possible_boards
+    pub fn boards(
+        &mut self,
+        state: u64,
+        sr: &[usize; 5],
+        sc: &[usize; 5],
+        br: &[usize; 5],
+        bc: &[usize; 5],
+        level: usize,
+    ) -> (Vec<u64>, Vec<usize>, Vec<f64>)
+    {
+        if let Some((base_state, possible_boards, counts, weights)) = &self.base
+        {
+            if is_refinement(state, *base_state)
+            {
+                info!("Thread: Filtering the {} boards of an earlier position", possible_boards.len());
+                return filter_boards(state, possible_boards, counts, weights);
+            }
+        }
+
+        info!("Thread: Finding the possible boards from scratch");
+        let (possible_boards, counts, weights) = find_possible_boards(state, &sr, &sc, &br, &bc, level);
+        self.base = Some((state, possible_boards.clone(), counts.clone(), weights.clone()));
+
+        (possible_boards, counts, weights)
+    }
+}
+
+
+// every face-up square of before is face-up in state with the same symbol,
+// e.g. state can be reached from before by revealing squares
+pub fn is_refinement(state: u64, before: u64) -> bool
+{
+    // a state is just a board with face-down squares
+    is_possible_board_of_state(state, before)
+}
+
+
+// boards which fit state, grouped like find_possible_boards groups them
+fn filter_boards(
+    state: u64,
+    possible_boards: &Vec<u64>,
+    counts: &Vec<usize>,
+    weights: &Vec<f64>,
+) -> (Vec<u64>, Vec<usize>, Vec<f64>)
+{
+    let mut groups = Vec::with_capacity(counts.len());
+
+    let mut start = 0;
+    for (i, &count) in counts.iter().enumerate()
+    {
+        let boards: Vec<u64> = possible_boards[start..start + count]
+            .iter()
+            .filter(|&&board| is_possible_board_of_state(board, state))
+            .cloned()
+            .collect();
+
+        if !boards.is_empty()
+        {
+            groups.push((boards, weights[i]));
+        }
+
+        start += count;
+    }
+
+    // the enumeration finds boards in ascending order and opens a group with its first board,
+    // so the groups are in the order of their smallest boards
+    groups.sort_by_key(|(boards, _)| boards[0]);
+
+    let mut filtered = Vec::new();
+    let mut filtered_counts = Vec::with_capacity(groups.len());
+    let mut filtered_weights = Vec::with_capacity(groups.len());
+
+    for (boards, weight) in groups
+    {
+        filtered_counts.push(boards.len());
+        filtered_weights.push(weight);
+        filtered.extend(boards);
+    }
+
+    (filtered, filtered_counts, filtered_weights)
+}
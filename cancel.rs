@@ -0,0 +1,43 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+// Lets the GUI abort a running search without going through the channel.
+// Every cancel is followed by a Stop message, the search thread acknowledges it when it gets there.
+// A search is cancelled while a Stop is on its way, so a cancel can't get lost
+// between two searches and nothing has to be reset
+#[derive(Clone)]
+pub struct CancelToken
+{
+    requested: Arc<AtomicUsize>, // cancels by the GUI
+    acknowledged: Arc<AtomicUsize>, // Stop messages the search thread has handled
+}
+
+impl CancelToken
+{
+    pub fn new() -> CancelToken
+    {
+        CancelToken {
+            requested: Arc::new(AtomicUsize::new(0)),
+            acknowledged: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // GUI side, right before sending Stop
+    pub fn cancel(&self)
+    {
+        self.requested.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // search thread side, when receiving Stop
+    pub fn acknowledge(&self)
+    {
+        self.acknowledged.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // cheap enough to be checked at every node
+    pub fn is_cancelled(&self) -> bool
+    {
+        self.requested.load(Ordering::Relaxed) > self.acknowledged.load(Ordering::Relaxed)
+    }
+}
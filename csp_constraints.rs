@@ -1,4 +1,5 @@
-use crate::level_constraints::{get_weight_of_state, get_weights_array};
+use std::collections::HashMap;
+use crate::level_constraints::{get_constraint, get_weight_of_state, get_weights_array};
 use crate::packed::{array_to_u64, get_from_packed_state};
 use crate::math::print_board;
 
@@ -17,6 +18,244 @@ pub fn find_possible_boards(
 {
     let mut r = Vec::new();
 
+    let mut state = unpack_state(org_packed_state);
+    let (patterns, face_down_below, col_sums) = rows_to_combine(&state, &sr, &br);
+
+    sh_rows(&mut state, 0, &patterns, &face_down_below, col_sums, [0; 5], &sc, &bc, level, &mut r, &get_weights_array());
+
+    group_solutions(r)
+}
+
+
+// Draws possible boards by their level weight straight from the row patterns, without listing them.
+// The boards are filled row by row, partial boards with the same column sums, column Voltorbs and 3's
+// are one partial board. Each knows the weight of all ways to finish it, counting every board as heavy
+// as a board with its number of 3's can be at most. Drawing by those weights and keeping a board with
+// the chance of its real weight to that gives every board exactly its share of the level weight
+pub struct BoardSampler
+{
+    state: [[usize; 5]; 5],
+    patterns: Vec<Vec<[usize; 5]>>,
+    level: usize,
+    weights: [[f64; 10]; 9],
+    max_weight: [f64; 26], // most a board with that many 3's can weigh
+    partials: Vec<PartialBoard>,
+    empty: usize, // partial board with no rows filled
+}
+
+struct PartialBoard
+{
+    weight: f64, // summed max. weight of the boards that finish it
+    boards: usize, // boards that finish it
+    threes: usize,
+    next: Vec<(usize, usize)>, // pattern of the next row, partial board it leads to
+}
+
+// that many draws in a row without keeping a board: no board fits the level
+const MAX_REJECTIONS: usize = 1_000_000;
+
+impl BoardSampler
+{
+    pub fn new(
+        org_packed_state: u64,
+        sr: &[usize; 5],
+        sc: &[usize; 5],
+        br: &[usize; 5],
+        bc: &[usize; 5],
+        level: usize,
+    ) -> BoardSampler
+    {
+        let state = unpack_state(org_packed_state);
+        let (patterns, face_down_below, col_sums) = rows_to_combine(&state, &sr, &br);
+        let weights = get_weights_array();
+
+        let mut max_weight = [0.0; 26];
+        for index in 0..10
+        {
+            let (nr_symbols, _, _) = get_constraint(level, index);
+            max_weight[nr_symbols[3]] += weights[level][index];
+        }
+
+        let mut sampler = BoardSampler {
+            state,
+            patterns,
+            level,
+            weights,
+            max_weight,
+            partials: Vec::new(),
+            empty: 0,
+        };
+
+        sampler.empty = sampler.add_partial(0, col_sums, [0; 5], 0, &face_down_below, &sc, &bc, &mut HashMap::new());
+        sampler
+    }
+
+    // boards that fit the sums and Voltorbs, also the ones the level rules out
+    pub fn boards(&self) -> usize
+    {
+        self.partials[self.empty].boards
+    }
+
+    // one board, drawn by weight with next_f64 uniform in [0, 1). None if no board fits the level
+    pub fn draw<R: FnMut() -> f64>(&self, next_f64: &mut R) -> Option<u64>
+    {
+        if self.partials[self.empty].weight == 0.0
+        {
+            return None;
+        }
+
+        for _ in 0..MAX_REJECTIONS
+        {
+            let mut board = self.state;
+            let mut partial = self.empty;
+
+            for row in 0..5
+            {
+                let mut x = next_f64() * self.partials[partial].weight;
+
+                // the last one that can be drawn, in case rounding leaves a bit of x
+                let mut chosen = None;
+                for &(p, next) in &self.partials[partial].next
+                {
+                    let weight = self.partials[next].weight;
+                    if weight > 0.0
+                    {
+                        chosen = Some((p, next));
+                        if x < weight
+                        {
+                            break;
+                        }
+                        x -= weight;
+                    }
+                }
+
+                let (p, next) = chosen.expect("A partial board with weight has a next one with weight");
+                board[row] = self.patterns[row][p];
+                partial = next;
+            }
+
+            if next_f64() * self.max_weight[self.partials[partial].threes] < get_weight_of_state(&board, self.level, &self.weights)
+            {
+                return Some(array_to_u64(&board));
+            }
+        }
+
+        None
+    }
+
+    // index of the partial board with these rows filled, added with all ways to finish it if it's new
+    fn add_partial(
+        &mut self,
+        row: usize,
+        sums: [usize; 5],
+        bombs: [usize; 5],
+        threes: usize,
+        face_down_below: &[[usize; 5]; 5],
+        sc: &[usize; 5],
+        bc: &[usize; 5],
+        known: &mut HashMap<(usize, [usize; 5], [usize; 5], usize), usize>,
+    ) -> usize
+    {
+        if let Some(&partial) = known.get(&(row, sums, bombs, threes))
+        {
+            return partial;
+        }
+
+        let mut partial = PartialBoard {
+            weight: 0.0,
+            boards: 0,
+            threes,
+            next: Vec::new(),
+        };
+
+        if row == 5
+        {
+            partial.weight = self.max_weight[threes];
+            partial.boards = 1;
+        }
+        else {
+            for p in 0..self.patterns[row].len()
+            {
+                let pattern = self.patterns[row][p];
+                if let Some((s, b)) = add_row(&self.state[row], &pattern, &face_down_below[row], sums, bombs, sc, bc)
+                {
+                    let t = threes + pattern.iter().filter(|&&symbol| symbol == 3).count();
+                    let next = self.add_partial(row + 1, s, b, t, face_down_below, sc, bc, known);
+                    partial.weight += self.partials[next].weight;
+                    partial.boards += self.partials[next].boards;
+                    partial.next.push((p, next));
+                }
+            }
+        }
+
+        self.partials.push(partial);
+        known.insert((row, sums, bombs, threes), self.partials.len() - 1);
+        self.partials.len() - 1
+    }
+}
+
+
+// every way to fill each row on its own (the search only has to combine them), for each row and column
+// the face-down squares of the rows below it and the sums of the revealed squares of each column
+fn rows_to_combine(
+    state: &[[usize; 5]; 5],
+    sr: &[usize; 5],
+    br: &[usize; 5],
+) -> (Vec<Vec<[usize; 5]>>, [[usize; 5]; 5], [usize; 5])
+{
+    let patterns: Vec<Vec<[usize; 5]>> = (0..5)
+        .map(|row| row_patterns(&state[row], sr[row], br[row]))
+        .collect();
+
+    // the revealed squares are part of the column sums from the start,
+    // what's left to place in a column are the face-down squares of the rows below
+    let mut col_sums = [0; 5];
+    let mut face_down_below = [[0; 5]; 5];
+    for c in 0..5
+    {
+        for row in 0..5
+        {
+            if state[row][c] > 3
+            {
+                for above in 0..row
+                {
+                    face_down_below[above][c] += 1;
+                }
+            }
+            else {
+                col_sums[c] += state[row][c];
+            }
+        }
+    }
+
+    (patterns, face_down_below, col_sums)
+}
+
+
+// the old enumeration, square by square in row-major order with the full check after every assignment.
+// Returns the same boards in the same order, kept to compare against
+pub fn find_possible_boards_square_by_square(
+    org_packed_state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+) -> (Vec<u64>, Vec<usize>, Vec<f64>)
+{
+    let mut r = Vec::new();
+
+    let mut state = unpack_state(org_packed_state);
+
+    sh_constraints(&mut state, 0, 0, &sr, &sc, &br, &bc, level, &mut r, &get_weights_array());
+
+    group_solutions(r)
+}
+
+
+// revealed squares as they are, 127 for face-down ones
+fn unpack_state(org_packed_state: u64) -> [[usize; 5]; 5]
+{
     let mut state = [[127; 5]; 5];
     for r in 0..5
     {
@@ -29,9 +268,13 @@ pub fn find_possible_boards(
             }
         }
     }
+    state
+}
 
-    sh_constraints(&mut state, 0, 0, &sr, &sc, &br, &bc, level, &mut r, &get_weights_array());
 
+// boards of all weight groups in one list, the counts and the weights
+fn group_solutions(r: Vec<(Vec<u64>, f64)>) -> (Vec<u64>, Vec<usize>, Vec<f64>)
+{
     let mut possible_boards = Vec::new();
     let mut counts = Vec::with_capacity(r.len());
     let mut weights = Vec::with_capacity(r.len());
@@ -50,46 +293,193 @@ pub fn find_possible_boards(
 }
 
 
-fn sh_constraints(
+// every assignment of a row (lexicographic, like the square by square search)
+// with exactly that sum and Voltorbs that keeps the revealed squares
+fn row_patterns(
+    line: &[usize; 5],
+    sum_line: usize,
+    bombs_line: usize,
+) -> Vec<[usize; 5]>
+{
+    let mut patterns = Vec::new();
+
+    for code in 0..1024
+    {
+        let mut pattern = [0; 5];
+        for c in 0..5
+        {
+            pattern[c] = (code >> ((4 - c) * 2)) & 3;
+        }
+
+        if (0..5).any(|c| line[c] <= 3 && line[c] != pattern[c])
+        {
+            continue;
+        }
+
+        let sum: usize = pattern.iter().sum();
+        let bombs = pattern.iter().filter(|&&symbol| symbol == 0).count();
+
+        if sum == sum_line && bombs == bombs_line
+        {
+            patterns.push(pattern);
+        }
+    }
+
+    patterns
+}
+
+
+// fills the board row by row, columns are checked with their partial sums after each row
+fn sh_rows(
     state: &mut [[usize; 5]; 5],
     row: usize,
-    col: usize,
-    sr: &[usize; 5],
+    patterns: &Vec<Vec<[usize; 5]>>,
+    face_down_below: &[[usize; 5]; 5],
+    col_sums: [usize; 5],
+    col_bombs: [usize; 5],
     sc: &[usize; 5],
-    br: &[usize; 5],
     bc: &[usize; 5],
     level: usize,
     solutions: &mut Vec<(Vec<u64>, f64)>,
     cache: &[[f64; 10]; 9],
 )
 {
-    if !fsf(&state, &sr, &sc, &br, &bc)
+    if row == 5
     {
+        add_solution(&state, level, solutions, &cache);
         return;
     }
 
-    if row == 5
+    let original = state[row];
+
+    for pattern in &patterns[row]
     {
-        let weight = get_weight_of_state(&state, level, &cache);
-        if weight > 0.0 // fits in at least one group of the level
+        if let Some((sums, bombs)) = add_row(&original, pattern, &face_down_below[row], col_sums, col_bombs, sc, bc)
         {
-            let packed_state = array_to_u64(&state);
-            let mut contains = false;
-            for i in 0..solutions.len()
+            state[row] = *pattern;
+            sh_rows(state, row + 1, patterns, face_down_below, sums, bombs, sc, bc, level, solutions, cache);
+        }
+    }
+
+    state[row] = original;
+}
+
+
+// column sums and Voltorbs after filling a row (as it was: revealed squares are already in the sums)
+// with that pattern, None if a column can't fit its constraints anymore
+fn add_row(
+    original: &[usize; 5],
+    pattern: &[usize; 5],
+    face_down_below: &[usize; 5],
+    mut sums: [usize; 5],
+    mut bombs: [usize; 5],
+    sc: &[usize; 5],
+    bc: &[usize; 5],
+) -> Option<([usize; 5], [usize; 5])>
+{
+    for c in 0..5
+    {
+        if original[c] > 3
+        {
+            if pattern[c] == 0
             {
-                let (pbs,w) = &mut solutions[i];
-                if weight == *w
-                {
-                    pbs.push(packed_state);
-                    contains = true;
-                    break;
-                }
+                bombs[c] += 1;
+            } else {
+                sums[c] += pattern[c];
             }
-            if contains == false
+        }
+
+        if !fsf_partial_line(sums[c], bombs[c], face_down_below[c], sc[c], bc[c])
+        {
+            return None;
+        }
+    }
+
+    Some((sums, bombs))
+}
+
+
+// same as fsf_line when only the sum, the Voltorbs and the number of free squares of a line are known
+fn fsf_partial_line(
+    sum: usize,
+    bombs: usize,
+    free: usize,
+    sum_line: usize,
+    bombs_line: usize,
+) -> bool
+{
+    if sum > sum_line || bombs > bombs_line
+    {
+        return false;
+    }
+
+    // bomb squares needed
+    let bn = bombs_line - bombs;
+    if bn > free
+    {
+        return false;
+    }
+
+    // squares left for points have to make up the rest of the sum with 1's to 3's
+    let fs = free - bn;
+    let sn = sum_line - sum;
+
+    fs <= sn && sn <= fs * 3
+}
+
+
+// adds a fully assigned board to the group of its weight
+fn add_solution(
+    state: &[[usize; 5]; 5],
+    level: usize,
+    solutions: &mut Vec<(Vec<u64>, f64)>,
+    cache: &[[f64; 10]; 9],
+)
+{
+    let weight = get_weight_of_state(&state, level, &cache);
+    if weight > 0.0 // fits in at least one group of the level
+    {
+        let packed_state = array_to_u64(&state);
+        let mut contains = false;
+        for i in 0..solutions.len()
+        {
+            let (pbs,w) = &mut solutions[i];
+            if weight == *w
             {
-                solutions.push((vec![packed_state], weight));
+                pbs.push(packed_state);
+                contains = true;
+                break;
             }
         }
+        if contains == false
+        {
+            solutions.push((vec![packed_state], weight));
+        }
+    }
+}
+
+
+fn sh_constraints(
+    state: &mut [[usize; 5]; 5],
+    row: usize,
+    col: usize,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    solutions: &mut Vec<(Vec<u64>, f64)>,
+    cache: &[[f64; 10]; 9],
+)
+{
+    if !fsf(&state, &sr, &sc, &br, &bc)
+    {
+        return;
+    }
+
+    if row == 5
+    {
+        add_solution(&state, level, solutions, &cache);
         return;
     }
 
@@ -108,7 +498,7 @@ fn sh_constraints(
 
 
 // feasible so far, sophisticated pruning, checks correctly even if fully assigned
-fn fsf(
+pub fn fsf(
     a: &[[usize; 5]; 5],
     sr: &[usize; 5],
     sc: &[usize; 5],
@@ -119,140 +509,96 @@ fn fsf(
     // rows
     for row in 0..5
     {
-        let mut sum = 0;
-        let mut bombs = 0;
-        let mut ass = 0;
-
-        for col in 0..5
+        if !fsf_line(&a[row], sr[row], br[row])
         {
-            if a[row][col] == 1 ||
-                a[row][col] == 2 ||
-                a[row][col] == 3
-            {
-                sum += a[row][col];
-                ass += 1;
-            }
-            else if a[row][col] == 0
-            {
-                bombs += 1;
-                ass += 1;
-            }
+            return false;
         }
+    }
 
-        if ass == 5 // no more assignments left --> needs to fit exactly
+    // cols
+    for col in 0..5
+    {
+        let line = [a[0][col], a[1][col], a[2][col], a[3][col], a[4][col]];
+        if !fsf_line(&line, sc[col], bc[col])
         {
-            if sum != sr[row] || bombs != br[row]
-            {
-                return false;
-            }
-        } else { // just check if it's still within the limits
-
-            // simple check
-            if sum > sr[row] || bombs > br[row]
-            {
-                return false;
-            }
-
-            // more sophisticated check
-
-            // bomb squares needed
-            let bn = br[row] - bombs;
-
-            // no more free squares e.g. more bombs left than squares available?
-            if 5 < ass + bn
-            {
-                return false;
-            }
-
-            // free squares for points after taking away assigned and squares needed for bombs
-            let fs = 5 - ass - bn;
+            return false;
+        }
+    }
 
-            // sum needed, points left to reach sr
-            let sn = sr[row] - sum;
+    true
+}
 
-            // two cases
 
-            //  too little sn, will be exceeded even with 1's
-            if sn < fs
-            {
-                return false;
-            }
+// the same check for a single row or column, values above 3 are unassigned
+pub fn fsf_line(
+    line: &[usize; 5],
+    sum_line: usize,
+    bombs_line: usize,
+) -> bool
+{
+    let mut sum = 0;
+    let mut bombs = 0;
+    let mut ass = 0;
 
-            // too much sn, can't be reached even with 3's
-            if sn > fs * 3
-            {
-                return false;
-            }
+    for i in 0..5
+    {
+        if line[i] == 1 ||
+            line[i] == 2 ||
+            line[i] == 3
+        {
+            sum += line[i];
+            ass += 1;
+        }
+        else if line[i] == 0
+        {
+            bombs += 1;
+            ass += 1;
         }
     }
 
-    // cols
-    for col in 0..5
+    if ass == 5 // no more assignments left --> needs to fit exactly
     {
-        let mut sum = 0;
-        let mut bombs = 0;
-        let mut ass = 0;
-
-        for row in 0..5
+        if sum != sum_line || bombs != bombs_line
         {
-            if a[row][col] == 1 ||
-                a[row][col] == 2 ||
-                a[row][col] == 3
-            {
-                sum += a[row][col];
-                ass += 1;
-            }
-            else if a[row][col] == 0
-            {
-                bombs += 1;
-                ass += 1;
-            }
+            return false;
         }
+    } else { // just check if it's still within the limits
 
-        if ass == 5 // no more assignments left --> needs to fit exactly
+        // simple check
+        if sum > sum_line || bombs > bombs_line
         {
-            if sum != sc[col] || bombs != bc[col]
-            {
-                return false;
-            }
-        } else { // just check if it's still within the limits
-
-            // simple check
-            if sum > sc[col] || bombs > bc[col]
-            {
-                return false;
-            }
+            return false;
+        }
 
-            // more sophisticated check
+        // more sophisticated check
 
-            // bomb squares needed
-            let bn = bc[col] - bombs;
+        // bomb squares needed
+        let bn = bombs_line - bombs;
 
-            // no more free squares e.g. more bombs left than squares available?
-            if  5 < ass + bn
-            {
-                return false;
-            }
+        // no more free squares e.g. more bombs left than squares available?
+        if 5 < ass + bn
+        {
+            return false;
+        }
 
-            // free squares for points after taking away assigned and squares needed for bombs
-            let fs = 5 - ass - bn;
+        // free squares for points after taking away assigned and squares needed for bombs
+        let fs = 5 - ass - bn;
 
-            // sum needed, points left to reach sr
-            let sn = sc[col] - sum;
+        // sum needed, points left to reach the sum of the line
+        let sn = sum_line - sum;
 
-            // two cases
+        // two cases
 
-            //  too little sn, will be exceeded even with 1's
-            if sn < fs
-            {
-                return false;
-            }
+        //  too little sn, will be exceeded even with 1's
+        if sn < fs
+        {
+            return false;
+        }
 
-            // too much sn, can't be reached even with 3's
-            if sn > fs * 3
-            {
-                return false;
-            }
+        // too much sn, can't be reached even with 3's
+        if sn > fs * 3
+        {
+            return false;
         }
     }
 
@@ -0,0 +1,281 @@
+use crate::csp_constraints::find_possible_boards;
+use crate::packed::get_from_packed_state;
+
+
+// what is known for sure about a face-down square
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Certainty
+{
+    Voltorb,
+    One,
+    Two,
+    Three,
+    Safe, // not a Voltorb, but could be more than one number
+    Useless, // Voltorb or 1, can't be a 2 or 3
+}
+
+// why it's known, line facts are about what's left after taking away the face-up squares
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reason
+{
+    RowWithoutVoltorbs(usize),
+    ColWithoutVoltorbs(usize),
+    RowFullOfVoltorbs(usize), // as many Voltorbs left as face-down squares
+    ColFullOfVoltorbs(usize),
+    RowOnlyOnesLeft(usize), // points left = face-down squares - Voltorbs left
+    ColOnlyOnesLeft(usize),
+    RowOnlyThreesLeft(usize), // points left = 3 * (face-down squares - Voltorbs left)
+    ColOnlyThreesLeft(usize),
+    AllBoards, // no single line explains it, but every possible board of that level agrees
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Deduction
+{
+    pub row: usize,
+    pub col: usize,
+    pub certainty: Certainty,
+    pub reasons: Vec<Reason>, // all of them together explain the certainty
+}
+
+
+// squares which are the same in every possible board (or at least never a Voltorb / 2 / 3),
+// without any probabilities or search, None if there's no possible board at all
+pub fn find_deductions(
+    org_packed_state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+) -> Option<Vec<Deduction>>
+{
+    let (possible_boards, _, _) = find_possible_boards(org_packed_state, &sr, &sc, &br, &bc, level);
+
+    find_deductions_with_boards(org_packed_state, &possible_boards, &sr, &sc, &br, &bc)
+}
+
+// same as find_deductions with the possible boards (as returned by find_possible_boards) already known
+pub fn find_deductions_with_boards(
+    org_packed_state: u64,
+    possible_boards: &Vec<u64>,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+) -> Option<Vec<Deduction>>
+{
+    if possible_boards.is_empty()
+    {
+        return None;
+    }
+
+    // bit s is set if symbol s occurs on that square in some board
+    let mut symbols = [[0_usize;5];5];
+    for &board in possible_boards
+    {
+        for row in 0..5
+        {
+            for col in 0..5
+            {
+                symbols[row][col] |= 1 << get_from_packed_state(board, row, col);
+            }
+        }
+    }
+
+    let (row_facts, col_facts) = line_facts(org_packed_state, &sr, &sc, &br, &bc);
+
+    let mut deductions = Vec::new();
+    for row in 0..5
+    {
+        for col in 0..5
+        {
+            if get_from_packed_state(org_packed_state, row, col) != 0
+            {
+                continue;
+            }
+
+            let certainty = match symbols[row][col]
+            {
+                0b0001 => Certainty::Voltorb,
+                0b0010 => Certainty::One,
+                0b0100 => Certainty::Two,
+                0b1000 => Certainty::Three,
+                m if m & 0b0001 == 0 => Certainty::Safe,
+                m if m & 0b1100 == 0 => Certainty::Useless,
+                _ => continue, // nothing certain about it
+            };
+
+            let reasons = explain_certainty(certainty, &row_facts[row], &col_facts[col]);
+
+            deductions.push(Deduction {
+                row,
+                col,
+                certainty,
+                reasons,
+            });
+        }
+    }
+
+    Some(deductions)
+}
+
+
+// (no Voltorbs, full of Voltorbs, only ones, only threes) for every line,
+// each as the reason it would be for that line
+fn line_facts(
+    packed_state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+) -> ([[Option<Reason>;4];5], [[Option<Reason>;4];5])
+{
+    let mut row_facts = [[None;4];5];
+    let mut col_facts = [[None;4];5];
+
+    for i in 0..5
+    {
+        // face-up squares are never Voltorbs, so only the points change
+        let mut row_hidden = 0;
+        let mut row_points = sr[i];
+        let mut col_hidden = 0;
+        let mut col_points = sc[i];
+
+        for j in 0..5
+        {
+            match get_from_packed_state(packed_state, i, j)
+            {
+                0 => row_hidden += 1,
+                s => row_points = row_points.saturating_sub(s),
+            }
+
+            match get_from_packed_state(packed_state, j, i)
+            {
+                0 => col_hidden += 1,
+                s => col_points = col_points.saturating_sub(s),
+            }
+        }
+
+        row_facts[i] = facts_of_line(
+            row_hidden, row_points, br[i],
+            [Reason::RowWithoutVoltorbs(i), Reason::RowFullOfVoltorbs(i),
+                Reason::RowOnlyOnesLeft(i), Reason::RowOnlyThreesLeft(i)]);
+
+        col_facts[i] = facts_of_line(
+            col_hidden, col_points, bc[i],
+            [Reason::ColWithoutVoltorbs(i), Reason::ColFullOfVoltorbs(i),
+                Reason::ColOnlyOnesLeft(i), Reason::ColOnlyThreesLeft(i)]);
+    }
+
+    (row_facts, col_facts)
+}
+
+
+fn facts_of_line(
+    hidden: usize,
+    points: usize,
+    bombs: usize,
+    reasons: [Reason;4],
+) -> [Option<Reason>;4]
+{
+    let mut facts = [None;4];
+
+    if bombs == 0
+    {
+        facts[0] = Some(reasons[0]);
+    }
+
+    if bombs == hidden
+    {
+        facts[1] = Some(reasons[1]);
+    }
+
+    if bombs < hidden
+    {
+        let numbers = hidden - bombs;
+
+        if points == numbers
+        {
+            facts[2] = Some(reasons[2]);
+        }
+
+        if points == 3 * numbers
+        {
+            facts[3] = Some(reasons[3]);
+        }
+    }
+
+    facts
+}
+
+
+// pick line facts which together imply the certainty, AllBoards if they can't
+fn explain_certainty(
+    certainty: Certainty,
+    row_facts: &[Option<Reason>;4],
+    col_facts: &[Option<Reason>;4],
+) -> Vec<Reason>
+{
+    // first fact found in the row or the column
+    let fact = |i: usize| row_facts[i].or(col_facts[i]);
+
+    let not_a_voltorb = fact(0);
+    let voltorb = fact(1);
+    let only_ones = fact(2);
+    let only_threes = fact(3);
+
+    let parts = match certainty
+    {
+        Certainty::Voltorb => vec![voltorb],
+        Certainty::One => vec![not_a_voltorb, only_ones],
+        Certainty::Three => vec![not_a_voltorb, only_threes],
+        Certainty::Safe => vec![not_a_voltorb],
+        Certainty::Useless => vec![only_ones],
+        Certainty::Two => vec![None], // no single line sum forces a 2
+    };
+
+    if parts.iter().all(|p| p.is_some())
+    {
+        let mut reasons: Vec<Reason> = parts.into_iter().map(|p| p.unwrap()).collect();
+        reasons.dedup();
+        reasons
+    }
+    else {
+        vec![Reason::AllBoards]
+    }
+}
+
+
+// e.g. "(2, 3) is a 1: row 2 has no Voltorbs and column 3 has only 1s left",
+// rows and columns counted from 1 like in the GUI
+pub fn describe_deduction(deduction: &Deduction) -> String
+{
+    let certainty = match deduction.certainty
+    {
+        Certainty::Voltorb => "a Voltorb",
+        Certainty::One => "a 1",
+        Certainty::Two => "a 2",
+        Certainty::Three => "a 3",
+        Certainty::Safe => "safe",
+        Certainty::Useless => "a Voltorb or a 1",
+    };
+
+    let reasons: Vec<String> = deduction.reasons
+        .iter()
+        .map(|reason| match *reason
+        {
+            Reason::RowWithoutVoltorbs(i) => format!("row {} has no Voltorbs", i + 1),
+            Reason::ColWithoutVoltorbs(i) => format!("column {} has no Voltorbs", i + 1),
+            Reason::RowFullOfVoltorbs(i) => format!("row {} has as many Voltorbs as face-down squares", i + 1),
+            Reason::ColFullOfVoltorbs(i) => format!("column {} has as many Voltorbs as face-down squares", i + 1),
+            Reason::RowOnlyOnesLeft(i) => format!("row {} has only 1s left (points = squares - Voltorbs)", i + 1),
+            Reason::ColOnlyOnesLeft(i) => format!("column {} has only 1s left (points = squares - Voltorbs)", i + 1),
+            Reason::RowOnlyThreesLeft(i) => format!("row {} has only 3s left (points = 3 * (squares - Voltorbs))", i + 1),
+            Reason::ColOnlyThreesLeft(i) => format!("column {} has only 3s left (points = 3 * (squares - Voltorbs))", i + 1),
+            Reason::AllBoards => "every possible board of that level agrees".to_string(),
+        })
+        .collect();
+
+    format!("({}, {}) is {}: {}", deduction.row + 1, deduction.col + 1, certainty, reasons.join(" and "))
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use crossbeam_channel::Sender;
+use rayon::prelude::*;
+use crate::approx::{sh_bounded, Spent};
+use crate::board_index::BoardIndex;
+use crate::cancel::CancelToken;
+use crate::csp_constraints::find_possible_boards;
+use crate::gui::{ReportMessage, SearchMode};
+use crate::math::count_assigned_packed;
+use crate::packed::{get_from_packed_state, set_in_packed_state};
+use crate::search::{candidate_squares, resign_value, optimistic_value, SearchResult};
+use crate::symmetry::Symmetries;
+
+
+// estimates the value of a state the depth-limited search doesn't look beyond
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Heuristic
+{
+    SafeSquareProduct, // chance to flip the squares that still matter without a Voltorb, as if they were independent
+    NoTwoOrThreeLeft, // share of the boards left that are already won
+}
+
+impl Heuristic
+{
+    // never more than the best value possible below that state, so the cuts of sh_bounded_square stay exact
+    fn evaluate(
+        &self,
+        state: u64,
+        board_index: &BoardIndex,
+        boards: &[u64],
+        level: usize,
+        mode: SearchMode,
+    ) -> f64
+    {
+        let chance = match self
+        {
+            Heuristic::SafeSquareProduct => safe_square_product(state, &board_index.accumulate(boards), level, mode),
+            Heuristic::NoTwoOrThreeLeft => board_index.won_share(state, boards),
+        };
+
+        resign_value(state, mode).max(chance * optimistic_value(board_index, boards, mode))
+    }
+}
+
+
+// Expectimax like compute_win_chance_exact, but only max_flips flips deep, below that
+// the heuristic guesses the value. 1 is as cheap as SurviveNextMove, 25 is the exact search.
+// Reports symbol probabilities and the values of the squares to to_gui the same way,
+// SuccessfulSearchWithInfo(value, seconds, nodes)
+pub fn compute_win_chance_depth_limited(
+    org_packed_state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    cancel: &CancelToken,
+    to_gui: &Sender<ReportMessage>,
+    mode: SearchMode,
+    threads: usize,
+    max_flips: usize,
+    heuristic: Heuristic,
+) -> SearchResult
+{
+    let (o_pb, o_count, weights) = find_possible_boards(org_packed_state, &sr, &sc, &br, &bc, level);
+
+    compute_depth_limited_with_boards(
+        org_packed_state,
+        &o_pb, &o_count, &weights, false,
+        &sr, &sc, &br, &bc, level,
+        cancel, to_gui,
+        mode, threads, max_flips, heuristic)
+}
+
+// same as compute_win_chance_depth_limited with the possible boards of the original state
+// (as returned by find_possible_boards) already known, or only a sample of them if is_sample
+pub fn compute_depth_limited_with_boards(
+    org_packed_state: u64,
+    o_pb: &Vec<u64>,
+    o_count: &Vec<usize>,
+    weights: &Vec<f64>,
+    is_sample: bool,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    cancel: &CancelToken,
+    to_gui: &Sender<ReportMessage>,
+    mode: SearchMode,
+    threads: usize,
+    max_flips: usize,
+    heuristic: Heuristic,
+) -> SearchResult
+{
+    let start_of_computation = Instant::now();
+
+    if o_pb.is_empty()
+    {
+        return SearchResult::InconsistentPuzzle;
+    }
+
+    let board_index = BoardIndex::new(&o_pb, &o_count, &weights);
+    let root_set = board_index.root_set();
+
+    if board_index.is_won(org_packed_state, &root_set)
+    {
+        return SearchResult::TerminalState;
+    }
+
+    let acc = board_index.accumulate(&root_set);
+    to_gui.send(ReportMessage::SquareSymbols(acc.clone()))
+        .expect("Failed to send symbol prob array to GUI");
+
+    // a sample isn't closed under the symmetries
+    let symmetries = if is_sample { Symmetries::none() } else { Symmetries::new(&sr, &sc, &br, &bc) };
+    let nodes = AtomicUsize::new(0);
+
+    let mut squares = Vec::with_capacity(25);
+    candidate_squares(org_packed_state, &acc, &sr, &sc, &br, &bc, &symmetries, mode, &mut squares);
+
+    // below max_flips the heuristic is all there is
+    let leaf = |state: u64, boards: &[u64]| {
+        let v = heuristic.evaluate(state, &board_index, boards, level, mode);
+        (v, v)
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("Failed to build search thread pool");
+
+    // one square per job, each with its own boards and cache
+    let values: Vec<Option<f64>> = pool.install(|| squares.par_iter().map(|&(row, col)| {
+        let mut board_sets = board_index.sets_by_depth();
+        let mut cache = HashMap::new();
+        let mut spent = Spent::unbudgeted(cancel);
+
+        let mut value = 0.0;
+        let mut outcome_values = [None;4];
+
+        for symbol in 1..4
+        {
+            if acc[row][col][symbol] == 0.0
+            {
+                continue;
+            }
+
+            // the leaf gives equal bounds, so they are the value
+            let (v, _) = sh_bounded(
+                1, max_flips,
+                set_in_packed_state(org_packed_state, row, col, symbol),
+                (row, col, symbol),
+                &board_index, &mut board_sets,
+                &sr, &sc, &br, &bc, level,
+                &symmetries, &mut cache, &mut spent, &leaf, mode,
+            )?;
+
+            value += v * acc[row][col][symbol];
+            outcome_values[symbol] = Some(v);
+        }
+
+        nodes.fetch_add(spent.nodes, Ordering::Relaxed);
+
+        // a bomb always ends the game with nothing
+        if acc[row][col][0] > 0.0
+        {
+            outcome_values[0] = Some(0.0);
+        }
+
+        to_gui.send(ReportMessage::SquareOutcomes(row, col, outcome_values))
+            .expect("Failed to send outcome values for square");
+        to_gui.send(ReportMessage::SquareWinProb(row, col, value))
+            .expect("Failed to send win probability for square");
+
+        Some(value)
+    }).collect());
+
+    if values.iter().any(|v| v.is_none())
+    {
+        return SearchResult::Aborted;
+    }
+
+    let value = values
+        .into_iter()
+        .flatten()
+        .fold(resign_value(org_packed_state, mode), f64::max);
+
+    SearchResult::SuccessfulSearchWithInfo(
+        value,
+        start_of_computation.elapsed().as_secs_f64(),
+        nodes.load(Ordering::Relaxed))
+}
+
+
+// chance that none of the squares still needed is a Voltorb, treating them as independent.
+// Winning needs every square that could be a 2 or 3, surviving only the safest few
+fn safe_square_product(
+    state: u64,
+    acc: &[[[f64;4];5];5],
+    level: usize,
+    mode: SearchMode,
+) -> f64
+{
+    let mut not_bomb = Vec::with_capacity(25);
+
+    for row in 0..5
+    {
+        for col in 0..5
+        {
+            if get_from_packed_state(state, row, col) == 0
+            {
+                let useful = acc[row][col][2] > 0.0 || acc[row][col][3] > 0.0;
+
+                if useful || mode == SearchMode::SurviveLevel || mode == SearchMode::SurviveEight
+                {
+                    not_bomb.push(1.0 - acc[row][col][0]);
+                }
+            }
+        }
+    }
+
+    let needed = match mode
+    {
+        SearchMode::SurviveLevel => level.saturating_sub(count_assigned_packed(state)),
+        SearchMode::SurviveEight => 8_usize.saturating_sub(count_assigned_packed(state)),
+        _ => not_bomb.len(),
+    };
+
+    // safest first
+    not_bomb.sort_unstable_by(|a, b| b.partial_cmp(a).expect("Probabilities must not be NaN"));
+    not_bomb.iter().take(needed).product()
+}
@@ -0,0 +1,327 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::convert::TryInto;
+use std::collections::HashSet;
+use std::path::Path;
+use crate::transposition::TranspositionTable;
+use memmap2::Mmap;
+use crate::math::count_assigned_packed;
+
+// File layout, everything little endian:
+// header: magic (8 bytes), version (u64), number of records (u64)
+// records: (fingerprint u64, packed state u64, value f64), sorted by fingerprint and state
+// so a memory-mapped file can be searched without loading it and two files can be merged
+const MAGIC: &[u8; 8] = b"VFSCACHE";
+const VERSION: u64 = 1;
+const HEADER_SIZE: usize = 24;
+const RECORD_SIZE: usize = 24;
+
+
+// read-only view of a cache file, looks up values without loading the file
+pub struct MappedCache
+{
+    mmap: Option<Mmap>, // None for an empty file
+    len: usize,
+}
+
+impl MappedCache
+{
+    pub fn open(path: &str) -> io::Result<MappedCache>
+    {
+        let file = File::open(path)?;
+        if file.metadata()?.len() == 0
+        {
+            return Ok(MappedCache { mmap: None, len: 0 });
+        }
+
+        // safe as long as nobody truncates the file while it's mapped,
+        // we only ever replace cache files by renaming
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_SIZE || &mmap[0..8] != MAGIC
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a cache file", path)));
+        }
+
+        if read_u64(&mmap, 8) != VERSION
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} has an unknown version", path)));
+        }
+
+        let len = read_u64(&mmap, 16) as usize;
+        if mmap.len() != HEADER_SIZE + len * RECORD_SIZE
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is truncated", path)));
+        }
+
+        Ok(MappedCache { mmap: Some(mmap), len })
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    // (fingerprint, state, value) of the i-th record
+    pub fn record(&self, i: usize) -> (u64, u64, f64)
+    {
+        let mmap = self.mmap.as_ref().expect("Empty cache file has no records");
+        let offset = HEADER_SIZE + i * RECORD_SIZE;
+        (
+            read_u64(mmap, offset),
+            read_u64(mmap, offset + 8),
+            f64::from_bits(read_u64(mmap, offset + 16)),
+        )
+    }
+
+    pub fn get(&self, fingerprint: u64, state: u64) -> Option<f64>
+    {
+        let i = self.lower_bound(fingerprint, state);
+        if i < self.len
+        {
+            let (f, s, v) = self.record(i);
+            if f == fingerprint && s == state
+            {
+                return Some(v);
+            }
+        }
+
+        None
+    }
+
+    // all records of that puzzle and mode are next to each other
+    pub fn range_of(&self, fingerprint: u64) -> std::ops::Range<usize>
+    {
+        self.lower_bound(fingerprint, 0)..self.lower_bound(fingerprint, u64::MAX)
+    }
+
+    // index of the first record not smaller than (fingerprint, state)
+    fn lower_bound(&self, fingerprint: u64, state: u64) -> usize
+    {
+        let mut lo = 0;
+        let mut hi = self.len;
+        while lo < hi
+        {
+            let mid = (lo + hi) / 2;
+            let (f, s, _) = self.record(mid);
+            if (f, s) < (fingerprint, state)
+            {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+}
+
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64
+{
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+
+// inserts every value of the puzzle and mode the cache is bound to, returns how many
+pub fn load_cache(
+    path: &str,
+    cache: &TranspositionTable,
+) -> io::Result<usize>
+{
+    let fingerprint = bound_fingerprint(cache)?;
+
+    if !Path::new(path).exists()
+    {
+        return Ok(0);
+    }
+
+    let mapped = MappedCache::open(path)?;
+    let range = mapped.range_of(fingerprint);
+    let loaded = range.len();
+
+    for i in range
+    {
+        let (_, state, value) = mapped.record(i);
+        cache.insert(state, value);
+    }
+
+    Ok(loaded)
+}
+
+
+// the values of states with at most max_assigned face-up squares as records for write_cache, sorted.
+// The rest of the tree is cheap to recompute from them
+pub fn cache_records(
+    cache: &TranspositionTable,
+    max_assigned: usize,
+) -> io::Result<Vec<(u64, u64, f64)>>
+{
+    let fingerprint = bound_fingerprint(cache)?;
+
+    let mut records = Vec::new();
+    cache.for_each(|state, value| {
+        if count_assigned_packed(state) <= max_assigned
+        {
+            records.push((fingerprint, state, value));
+        }
+    });
+    records.sort_unstable_by_key(|&(f, s, _)| (f, s));
+
+    Ok(records)
+}
+
+
+// adds the records (of one puzzle and mode, see cache_records) to the file. If it would hold more than
+// max_records, the puzzles and modes with the fewest records go first, they're the quickest to solve again.
+// The records just added always stay. Returns the number of records on disk
+pub fn write_cache(
+    path: &str,
+    records: &Vec<(u64, u64, f64)>,
+    max_records: usize,
+) -> io::Result<usize>
+{
+    let existing = read_records(path)?;
+    let mut merged = merge_records(records, &existing);
+
+    if merged.len() > max_records
+    {
+        let added = records.first().map(|&(f, _, _)| f);
+        merged = prune_records(merged, added, max_records);
+    }
+
+    write_records(path, &merged)?;
+    Ok(merged.len())
+}
+
+
+// combines two cache files (e.g. from different machines) into a third one
+pub fn merge_cache_files(
+    path_a: &str,
+    path_b: &str,
+    path_out: &str,
+) -> io::Result<usize>
+{
+    let merged = merge_records(&read_records(path_a)?, &read_records(path_b)?);
+    write_records(path_out, &merged)?;
+    Ok(merged.len())
+}
+
+
+// values of a cache that isn't bound can't be told apart from other puzzles
+fn bound_fingerprint(cache: &TranspositionTable) -> io::Result<u64>
+{
+    cache.fingerprint()
+        .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "Cache isn't bound to a puzzle and mode"))
+}
+
+
+fn read_records(path: &str) -> io::Result<Vec<(u64, u64, f64)>>
+{
+    if !Path::new(path).exists()
+    {
+        return Ok(Vec::new());
+    }
+
+    let mapped = MappedCache::open(path)?;
+    Ok((0..mapped.len()).map(|i| mapped.record(i)).collect())
+}
+
+
+// both sorted, a key in both is the same exact value anyway, so keep the first
+fn merge_records(
+    a: &Vec<(u64, u64, f64)>,
+    b: &Vec<(u64, u64, f64)>,
+) -> Vec<(u64, u64, f64)>
+{
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let mut i = 0;
+    let mut j = 0;
+
+    while i < a.len() || j < b.len()
+    {
+        let take_a = j == b.len() || (i < a.len() && (a[i].0, a[i].1) <= (b[j].0, b[j].1));
+        let record = if take_a { a[i] } else { b[j] };
+
+        match merged.last()
+        {
+            Some(&(f, s, _)) if (f, s) == (record.0, record.1) => (),
+            _ => merged.push(record),
+        }
+
+        if take_a
+        {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    merged
+}
+
+
+// drops whole puzzles and modes, the smallest first, until at most max_records are left (or only keep)
+fn prune_records(
+    records: Vec<(u64, u64, f64)>,
+    keep: Option<u64>,
+    max_records: usize,
+) -> Vec<(u64, u64, f64)>
+{
+    // sorted by fingerprint, so the records of one are next to each other
+    let mut sizes: Vec<(usize, u64)> = Vec::new();
+    for &(f, _, _) in &records
+    {
+        match sizes.last_mut()
+        {
+            Some((size, last)) if *last == f => *size += 1,
+            _ => sizes.push((1, f)),
+        }
+    }
+    sizes.sort_unstable();
+
+    let mut left = records.len();
+    let mut dropped = HashSet::new();
+    for (size, f) in sizes
+    {
+        if left <= max_records
+        {
+            break;
+        }
+
+        if Some(f) != keep
+        {
+            dropped.insert(f);
+            left -= size;
+        }
+    }
+
+    records.into_iter().filter(|(f, _, _)| !dropped.contains(f)).collect()
+}
+
+
+// writes next to the target and renames, so a crash never leaves half a cache file behind
+fn write_records(
+    path: &str,
+    records: &Vec<(u64, u64, f64)>,
+) -> io::Result<()>
+{
+    let tmp_path = format!("{}.tmp", path);
+
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(records.len() as u64).to_le_bytes())?;
+
+        for &(f, s, v) in records
+        {
+            writer.write_all(&f.to_le_bytes())?;
+            writer.write_all(&s.to_le_bytes())?;
+            writer.write_all(&v.to_bits().to_le_bytes())?;
+        }
+
+        writer.flush()?;
+    }
+
+    fs::rename(&tmp_path, path)
+}
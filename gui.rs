@@ -12,10 +12,9 @@ use crate::parsing::{string_to_level_and_constraints, hardest_5, examples_357};
 use std::convert::TryInto;
 use std::{thread, process};
 use std::collections::HashMap;
-use crate::search::{compute_win_chance_exact, SearchResult};
+use crate::search::{compute_win_chance_with_boards, SearchResult};
 use crate::packed::{array_to_u64, coins_of_state, u64_to_array};
 use fltk::misc::Tooltip;
-use dashmap::DashMap;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use fltk::menu::MenuFlag;
@@ -24,6 +23,14 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use crate::math::count_assigned_packed;
 use dashmap::mapref::multiple::RefMulti;
 use fltk::valuator::{Counter, CounterType};
+use crate::disk_cache::{load_cache, cache_records, write_cache};
+use crate::transposition::{TranspositionTable, ReplacementPolicy, ValuePrecision, fingerprint};
+use crate::board_session::{BoardSession, is_refinement};
+use crate::cancel::CancelToken;
+use crate::information::{symbol_entropy, most_informative_square, DEFAULT_MAX_BOMB_PROB};
+use crate::stats::{SearchStatistics, format_statistics};
+use crate::deductions::{find_deductions_with_boards, describe_deduction, Deduction};
+use crate::human_rules::{explain_forced_squares, describe_forced_square, ForcedSquare};
 
 // gui controlling the search
 #[derive(Copy, Clone, Debug)]
@@ -35,16 +42,24 @@ pub enum ControlMessage
 State([[usize;5];5]),
     Mode(SearchMode),
     Threads(usize),
+    CacheSize(usize), // megabytes
+    Exit, // after the last Stop, waits for the cache file
 }
 
 // the search reports it's results
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum ReportMessage
 {
     ConfirmStop, // no possible board for this constraints and level
 SquareSymbols([[[f64;4];5];5]), // chances to bomb/1/2/3 for that square
 SquareWinProb(usize, usize, f64), // win chance for that square: row, col, chance
+SquareOutcomes(usize, usize, [Option<f64>;4]), // value after each symbol of that square, None if impossible
+SquareBounds(usize, usize, f64, f64), // value of that square while it's searched: row, col, lower, upper
+RootBounds(f64, f64, usize, usize), // value of the position while it's searched: lower, upper, best square so far
 FinishedSuccessfully(f64, u64, usize), // successful search, report win chance, comp. time, nodes
+Statistics(SearchStatistics), // counters of each depth, after the search finished or was aborted
+Deductions(Vec<Deduction>), // squares that are the same in every possible board, before the search starts
+ForcedSquares(Vec<ForcedSquare>), // squares the human rules prove, with the proofs, before the search starts
 FinishedInconsistent,
     FinishedTerminalState
 }
@@ -61,6 +76,8 @@ pub enum ButtonMessage
     Reset,
     Mode(SearchMode),
     Threads,
+    CacheSize,
+    MaxBombChance,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -178,6 +195,93 @@ fn update_buttons(
     app::redraw();
 }
 
+// symbol chances of a square, what each symbol leads to and the value of the square itself,
+// followed by what logic alone says about it
+fn square_tooltip(
+    sp: &[f64;4],
+    outcomes: &Option<[Option<f64>;4]>,
+    val: f64,
+    mode: SearchMode,
+    logic: &str,
+) -> String
+{
+    let format_value = |v: f64| {
+        if mode != SearchMode::Coins
+        {
+            format!("{:.2}%", v * 100.0)
+        }
+        else {
+            format!("{:.2}", v)
+        }
+    };
+
+    let names = ["Bomb", "One", "Two", "Three"];
+    let mut tooltip = String::new();
+
+    for symbol in 0..4
+    {
+        tooltip.push_str(&format!("{}: {:.2}%", names[symbol], sp[symbol] * 100.0));
+
+        // bombs are worth nothing anyway
+        if symbol > 0
+        {
+            if let Some(values) = outcomes
+            {
+                if let Some(v) = values[symbol]
+                {
+                    tooltip.push_str(&format!(" -> {}", format_value(v)));
+                }
+            }
+        }
+
+        tooltip.push('\n');
+    }
+
+    tooltip.push_str(&format!("Information: {:.2} bits\n", symbol_entropy(sp)));
+    tooltip.push_str(&format_value(val));
+    tooltip.push_str(logic);
+    tooltip
+}
+
+// deductions about that square and the proof of the human rules, one per line, empty if there are none
+fn logic_tooltip(
+    deductions: &Vec<Deduction>,
+    forced_squares: &Vec<ForcedSquare>,
+    row: usize,
+    col: usize,
+) -> String
+{
+    let deduced = deductions
+        .iter()
+        .filter(|d| d.row == row && d.col == col)
+        .map(|d| format!("\n{}", describe_deduction(d)));
+
+    let proven = forced_squares
+        .iter()
+        .filter(|f| f.row == row && f.col == col)
+        .flat_map(|f| describe_forced_square(f))
+        .map(|line| format!("\n{}", line));
+
+    deduced.chain(proven).collect()
+}
+
+// marks the square information_report would pick, empty for every other square
+fn information_tooltip(
+    informative_square: Option<(usize, usize)>,
+    max_bomb_prob: f64,
+    row: usize,
+    col: usize,
+) -> String
+{
+    if informative_square == Some((row, col))
+    {
+        format!("\nMost informative square with at most {:.0}% bomb chance", max_bomb_prob * 100.0)
+    }
+    else {
+        String::new()
+    }
+}
+
 fn tell_thread_start(
     to_thread: &Sender<ControlMessage>,
 ) -> ()
@@ -189,14 +293,21 @@ fn tell_thread_start(
 fn tell_thread_to_stop_and_wait_till_it_is_stopped(
     to_thread: &Sender<ControlMessage>,
     from_thread: &Receiver<ReportMessage>,
+    cancel: &CancelToken,
 ) -> ()
 {
     info!("GUI: Signaling thread to stop");
+    // aborts a running search right away, the Stop behind it is confirmed once it's back in the loop.
+    // The token alone isn't enough: reports the search sent before it noticed are still in the channel
+    // and the GUI resets the board as soon as this returns. ConfirmStop comes after the last of them,
+    // and the thread acknowledging the Stop is what lets the next search run again
+    cancel.cancel();
     to_thread.send(ControlMessage::Stop).expect("Failed to signal Stop to thread");
 
     info!("GUI: Waiting for thread to confirm stop signal...");
 
     // wait for thread to confirm stop and eat the other messages while doing so
+    // every Stop gets exactly one confirmation, so none is left over for the next search
     loop {
         match from_thread.recv()
         {
@@ -204,22 +315,7 @@ fn tell_thread_to_stop_and_wait_till_it_is_stopped(
                 match msg
                 {
                     ReportMessage::ConfirmStop => {
-                        info!("GUI: Thread confirmed stop signal with abort");
-                        break;
-                    },
-
-                    ReportMessage::FinishedSuccessfully(_, _, _) => {
-                        info!("GUI: Thread confirmed stop signal with successful search");
-                        break;
-                    },
-
-                    ReportMessage::FinishedInconsistent => {
-                        info!("GUI: Thread confirmed stop signal with inconsistent search");
-                        break;
-                    },
-
-                    ReportMessage::FinishedTerminalState => {
-                        info!("GUI: Thread confirmed stop signal with terminal state");
+                        info!("GUI: Thread confirmed stop signal");
                         break;
                     },
 
@@ -286,11 +382,27 @@ fn tell_thread_threads(
         .expect("Failed to signal thread number to thread");
 }
 
-pub fn gui() -> ()
+fn tell_thread_to_exit(
+    to_thread: &Sender<ControlMessage>,
+) -> ()
 {
-    // 25 squares in the initial position, the number of active threads is controlled otherwise
-    rayon::ThreadPoolBuilder::new().num_threads(25).build_global().unwrap();
+    info!("GUI: Telling thread to exit");
+    to_thread.send(ControlMessage::Exit).expect("Failed to tell thread to exit")
+}
 
+fn tell_thread_cache_size(
+    to_thread: &Sender<ControlMessage>,
+    megabytes: usize,
+) -> ()
+{
+    info!("GUI: Sending new cache size to thread");
+    to_thread.send(
+        ControlMessage::CacheSize(megabytes))
+        .expect("Failed to signal cache size to thread");
+}
+
+pub fn gui() -> ()
+{
     let recommended_threads = max(1, num_cpus::get() - 2); // two logical cores to not lag
     let mut threads = recommended_threads + 2;
 
@@ -302,12 +414,34 @@ pub fn gui() -> ()
     const DESCRIPTION_SURVIVE_EIGHT: &str = "Maximizes the chance to flip 8 cards without loosing. Useful for reaching level 8.";
     const DESCRIPTION_COINS: &str = "Maximizes the expected number of coins. Useful if you just need some more.";
 
+    // values of puzzles solved before, so reopening them is instant
+    const CACHE_FILE: &str = "voltorb_flip_cache.bin";
+    // only states with at most this many more face-up squares than the root are written to disk
+    const PERSISTED_DEPTH: usize = 3;
+    // 24 bytes each, the puzzles with the fewest values are dropped from the file beyond that
+    const MAX_PERSISTED_RECORDS: usize = 10_000_000;
+    // the table drops the states above the position only every that many flips,
+    // going through all of it after every flip takes longer than the room is worth
+    const PRUNE_EVERY_FLIPS: usize = 4;
+
+    // tooltip of the window with the counters of each depth of the last search, for tuning
+    // (needs USE_DEPTH_STATISTICS in stats.rs, the search doesn't count them otherwise)
+    const SHOW_STATISTICS: bool = false;
+
+    // memory of the transposition table, too small only makes it slower
+    const DEFAULT_CACHE_MB: usize = 2048;
+    let mut cache_mb = DEFAULT_CACHE_MB;
+
+    // only for marking the most informative square, the search doesn't use it
+    let mut max_bomb_prob = DEFAULT_MAX_BOMB_PROB;
+
     let (to_thread, from_gui) = unbounded();
-    let to_thread_clone = to_thread.clone();
+    let cancel = CancelToken::new();
+    let cancel_clone = cancel.clone();
 
     let (to_gui, from_thread) = unbounded();
 
-    let _ = thread::spawn(move || {
+    let search_thread = thread::spawn(move || {
 
         // thread data structures, initialize with anything,
         // doesn't start calculating before the GUI tells it to anyway
@@ -321,10 +455,21 @@ pub fn gui() -> ()
         let mut bc = None;
         let mut mode: Option<SearchMode> = None;
         let mut threads = None;
+        let mut cache_mb = DEFAULT_CACHE_MB;
+
+        let mut big_cache = TranspositionTable::new(cache_mb, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+        // puzzle and mode whose values from disk are in big_cache
+        let mut loaded_from_disk = None;
 
-        // map of small partial maps for each mode
-        let caches: DashMap<SearchMode, DashMap<u64, f64>> = DashMap::new(); // TODO clear caches upon constraint change
-        let big_cache = DashMap::with_capacity(111_744_155);
+        // face-up squares of the position big_cache was last pruned at
+        let mut pruned_at = 0;
+
+        // writes the values of the last finished search to disk
+        let mut cache_writer: Option<thread::JoinHandle<()>> = None;
+
+        // possible boards, kept between moves
+        let mut session = BoardSession::new();
 
         loop {
             match from_gui.recv()
@@ -337,23 +482,58 @@ pub fn gui() -> ()
 
                         info!("Thread: Starting search");
 
-                        if let Some(entry) = caches.get(&mode.unwrap())
+                        // before filling it, so nothing of another puzzle or mode stays in there
+                        let fp = fingerprint(&sr.unwrap(), &sc.unwrap(), &br.unwrap(), &bc.unwrap(),
+                                             level.unwrap(), mode.unwrap());
+                        big_cache.bind(fp);
+
+                        if loaded_from_disk != Some(fp)
                         {
-                            let mini_cache = entry.value();
-                            for entry in mini_cache
+                            // it might be writing values of this puzzle and mode
+                            if let Some(writer) = cache_writer.take()
                             {
-                                big_cache.insert(*entry.key(), *entry.value());
+                                writer.join().expect("Cache writer panicked");
                             }
+
+                            match load_cache(CACHE_FILE, &big_cache)
+                            {
+                                Ok(n) => info!("Thread: Loaded {} values from {}", n, CACHE_FILE),
+                                Err(e) => warn!("Thread: Failed to load {}: {}", CACHE_FILE, e),
+                            }
+                            loaded_from_disk = Some(fp);
+                        }
+
+                        let (o_pb, o_count, weights) = session.boards(
+                            org_packed_state.unwrap(),
+                            &sr.unwrap(), &sc.unwrap(), &br.unwrap(), &bc.unwrap(),
+                            level.unwrap());
+
+                        // what logic alone tells, shown while the search is still running
+                        if let Some(deductions) = find_deductions_with_boards(
+                            org_packed_state.unwrap(), &o_pb,
+                            &sr.unwrap(), &sc.unwrap(), &br.unwrap(), &bc.unwrap())
+                        {
+                            to_gui.send(ReportMessage::Deductions(deductions))
+                                .expect("Failed to send deductions to GUI");
+                        }
+
+                        if let Some(forced) = explain_forced_squares(
+                            org_packed_state.unwrap(),
+                            &sr.unwrap(), &sc.unwrap(), &br.unwrap(), &bc.unwrap())
+                        {
+                            to_gui.send(ReportMessage::ForcedSquares(forced))
+                                .expect("Failed to send forced squares to GUI");
                         }
 
-                        let search_result = compute_win_chance_exact(
+                        let search_result = compute_win_chance_with_boards(
                             org_packed_state.unwrap(),
+                            &o_pb, &o_count, &weights, false,
                             &sr.unwrap(),
                             &sc.unwrap(),
                             &br.unwrap(),
                             &bc.unwrap(),
                             level.unwrap(),
-                            &big_cache, &from_gui, &to_gui, &to_thread_clone,
+                            &big_cache, &cancel_clone, &to_gui,
                             mode.unwrap(),
                             threads.unwrap());
 
@@ -378,9 +558,7 @@ pub fn gui() -> ()
                             }
 
                             SearchResult::Aborted => {
-                                info!("Thread: Search has been aborted, sending stop confirmation");
-                                to_gui.send(ReportMessage::ConfirmStop)
-                                    .expect("Sending stop confirmation failed");
+                                info!("Thread: Search has been aborted, the Stop is confirmed when it arrives");
                             }
 
                             SearchResult::InconsistentPuzzle => {
@@ -390,56 +568,75 @@ pub fn gui() -> ()
                             }
                         }
 
-                        // also keep the cache of aborted searches around
-
-                        // no cache for this mode? Create a new one
-                        if !caches.contains_key(&mode.unwrap())
+                        // only finished searches, a Stop never waits for the table to be read.
+                        // Writing the file takes longer, another thread does that
+                        if let SearchResult::SuccessfulSearchWithInfo(..) = search_result
                         {
-                            caches.insert(mode.unwrap(), DashMap::with_capacity(16_384));
-                        }
-
-                        // get the cache for the current mode
-                        if let Some(entry) = caches.get(&mode.unwrap())
-                        {
-                            let mini_cache = entry.value();
-                            // copy the most important content into that mini_cache
-                            // big_cache is the cache the algorithm uses
-                            for entry in &big_cache
+                            let max_assigned = count_assigned_packed(org_packed_state.unwrap()) + PERSISTED_DEPTH;
+                            match cache_records(&big_cache, max_assigned)
                             {
-                                let board = *entry.key();
-                                let value = *entry.value();
-
-                                let assigned = count_assigned_packed(board);
+                                Ok(records) => {
+                                    // one at a time, they all write the same file
+                                    if let Some(writer) = cache_writer.take()
+                                    {
+                                        writer.join().expect("Cache writer panicked");
+                                    }
 
-                                if assigned <= 3
-                                {
-                                    mini_cache.insert(board, value);
+                                    cache_writer = Some(thread::spawn(move || {
+                                        match write_cache(CACHE_FILE, &records, MAX_PERSISTED_RECORDS)
+                                        {
+                                            Ok(n) => info!("Cache writer: {} now holds {} values", CACHE_FILE, n),
+                                            Err(e) => warn!("Cache writer: Failed to save {}: {}", CACHE_FILE, e),
+                                        }
+                                    }));
                                 }
+
+                                Err(e) => warn!("Thread: Failed to save {}: {}", CACHE_FILE, e),
                             }
                         }
                     },
 
                     ControlMessage::Stop => {
-                        info!("Thread: Got stop signal but wasn't searching");
+                        info!("Thread: Got stop signal, confirming it");
+                        cancel_clone.acknowledge();
                         to_gui
                             .send(ReportMessage::ConfirmStop)
                             .expect("Failed to confirm stop to GUI");
                     },
 
                     ControlMessage::Constraints(sr_, sc_, br_, bc_, level_) => {
-                        info!("Thread: Received new constraints, clearing the caches");
+                        info!("Thread: Received new constraints, clearing the cache");
                         sr = Some(sr_);
                         sc = Some(sc_);
                         br = Some(br_);
                         bc = Some(bc_);
                         level = Some(level_);
-                        caches.clear();
                         big_cache.clear();
+                        loaded_from_disk = None;
+                        session.clear();
                     },
 
                     ControlMessage::State(state_) => {
                         info!("Thread: Received new state");
-                        org_packed_state = Some(array_to_u64(&state_));
+                        let new_state = array_to_u64(&state_);
+
+                        // squares were revealed: the values below the new position stay,
+                        // states with fewer face-up squares can't come up again and only take up room.
+                        // Only every PRUNE_EVERY_FLIPS flips, until then they keep their slots
+                        if let Some(old_state) = org_packed_state
+                        {
+                            let assigned = count_assigned_packed(new_state);
+
+                            if new_state != old_state && is_refinement(new_state, old_state) &&
+                                assigned >= pruned_at + PRUNE_EVERY_FLIPS
+                            {
+                                let removed = big_cache.retain(|s| count_assigned_packed(s) >= assigned);
+                                info!("Thread: Kept {} values below the new position, dropped {}", big_cache.len(), removed);
+                                pruned_at = assigned;
+                            }
+                        }
+
+                        org_packed_state = Some(new_state);
                     },
 
                     ControlMessage::Mode(m) => {
@@ -447,6 +644,7 @@ pub fn gui() -> ()
                         {
                             info!("Thread: Setting mode to {:?}", m);
                             big_cache.clear();
+                            loaded_from_disk = None;
                             mode = Some(m);
                         }
                         else
@@ -466,6 +664,32 @@ pub fn gui() -> ()
                             info!("Thread: Already using {} threads for calculation", t);
                         }
                     }
+
+                    ControlMessage::CacheSize(mb) => {
+                        if cache_mb != mb
+                        {
+                            info!("Thread: Resizing cache to {} MB", mb);
+                            cache_mb = mb;
+
+                            // free the old table before allocating the new one
+                            drop(big_cache);
+                            big_cache = TranspositionTable::new(cache_mb, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+                            loaded_from_disk = None;
+                        }
+                        else
+                        {
+                            info!("Thread: Cache already has {} MB", mb);
+                        }
+                    }
+
+                    ControlMessage::Exit => {
+                        info!("Thread: Exiting once the cache file is written");
+                        if let Some(writer) = cache_writer.take()
+                        {
+                            writer.join().expect("Cache writer panicked");
+                        }
+                        break;
+                    }
                 }
             }
         }
@@ -596,13 +820,13 @@ pub fn gui() -> ()
     window.show();
 
     let mut window2 = Window::default()
-        .with_size(400, 200)
+        .with_size(400, 260)
         .center_screen()
-        .with_label("Algorithm and Threads");
+        .with_label("Algorithm, Threads and Cache");
 
     window2.make_resizable(true);
 
-    let mut pack = group::Group::new(0, 0, 400, 200, "");
+    let mut pack = group::Group::new(0, 0, 400, 260, "");
     pack.end();
     pack.set_type(group::PackType::Vertical);
 
@@ -651,9 +875,43 @@ pub fn gui() -> ()
 
     counter.emit(sender_app, ButtonMessage::Threads);
 
+    let mut cache_counter = Counter::new(0, 200, 400, 30, "");
+    cache_counter.set_type(CounterType::Simple);
+
+    cache_counter.set_color(Color::White);
+    cache_counter.set_label_size(20);
+    cache_counter.set_tooltip("Cache size in MB, less than that is never used");
+
+    cache_counter.set_precision(0);
+    cache_counter.set_step(256.0, 1);
+    cache_counter.set_minimum(256.0);
+    cache_counter.set_maximum(65536.0);
+
+    cache_counter.set_value(cache_mb as f64);
+
+    cache_counter.emit(sender_app, ButtonMessage::CacheSize);
+
+    let mut bomb_counter = Counter::new(0, 230, 400, 30, "");
+    bomb_counter.set_type(CounterType::Simple);
+
+    bomb_counter.set_color(Color::White);
+    bomb_counter.set_label_size(20);
+    bomb_counter.set_tooltip("Bomb chance in % the most informative square may have at most");
+
+    bomb_counter.set_precision(0);
+    bomb_counter.set_step(5.0, 1);
+    bomb_counter.set_minimum(0.0);
+    bomb_counter.set_maximum(100.0);
+
+    bomb_counter.set_value(max_bomb_prob * 100.0);
+
+    bomb_counter.emit(sender_app, ButtonMessage::MaxBombChance);
+
     pack.add(&menu_choice);
     pack.add_resizable(&text_display);
     pack.add(&counter);
+    pack.add(&cache_counter);
+    pack.add(&bomb_counter);
 
     window2.end();
     window2.show();
@@ -713,10 +971,15 @@ pub fn gui() -> ()
     tell_thread_constraints(&to_thread, &sr, &sc, &br, &bc, level);
     tell_thread_state(&to_thread, &state);
     tell_thread_threads(&to_thread, threads);
+    tell_thread_cache_size(&to_thread, cache_mb);
     tell_thread_start(&to_thread);
 
     let mut symbol_probs: Option<[[[f64;4];5];5]> = None;
     let mut probs = [[None;5];5];
+    let mut outcomes = [[None;5];5];
+    let mut deductions = Vec::new();
+    let mut forced_squares = Vec::new();
+    let mut informative_square = None;
 
     while app::wait_for(1.0/600.0).expect("Crashed while waiting for something to happen")
     {
@@ -914,27 +1177,12 @@ pub fn gui() -> ()
                     let sp = symbol_probs.unwrap();
                     probs[row][col] = Some(val);
 
-                    if mode != SearchMode::Coins
-                    {
-                        square_buttons[row][col].set_tooltip(
-                            &format!("Bomb: {:.2}%\nOne: {:.2}%\nTwo: {:.2}%\n\
-                        Three: {:.2}%\n{:.2}%",
-                                     sp[row][col][0] * 100.0, sp[row][col][1] * 100.0,
-                                     sp[row][col][2] * 100.0, sp[row][col][3] * 100.0,
-                                     val * 100.0
-                            )
-                        );
-                    }
-                    else {
-                        square_buttons[row][col].set_tooltip(
-                            &format!("Bomb: {:.2}%\nOne: {:.2}%\nTwo: {:.2}%\n\
-                        Three: {:.2}%\n{:.2}",
-                                     sp[row][col][0] * 100.0, sp[row][col][1] * 100.0,
-                                     sp[row][col][2] * 100.0, sp[row][col][3] * 100.0,
-                                     val
-                            )
-                        );
-                    }
+                    square_buttons[row][col].set_tooltip(
+                        &square_tooltip(&sp[row][col], &outcomes[row][col], val, mode,
+                                        &format!("{}{}",
+                                                 information_tooltip(informative_square, max_bomb_prob, row, col),
+                                                 logic_tooltip(&deductions, &forced_squares, row, col)))
+                    );
 
                     let mut exists_safe_and_useful = false;
                     let mut best_win_chance = val;
@@ -992,9 +1240,68 @@ pub fn gui() -> ()
                     }
                 }
 
+                ReportMessage::SquareBounds(row, col, lower, upper) => {
+                    info!("GUI: Received bounds for square ({}, {}), between {} and {}", row, col, lower, upper);
+
+                    // a late message mustn't overwrite the final value
+                    if probs[row][col].is_none()
+                    {
+                        if mode != SearchMode::Coins
+                        {
+                            square_buttons[row][col].set_label(&format!("{:.0}-{:.0}%", lower * 100.0, upper * 100.0));
+                        }
+                        else
+                        {
+                            square_buttons[row][col].set_label(&format!("{:.0}-{:.0}", lower, upper));
+                        }
+                    }
+                }
+
+                ReportMessage::RootBounds(lower, upper, row, col) => {
+                    info!("GUI: Received bounds for the root, between {} and {}", lower, upper);
+
+                    let bounds = if mode != SearchMode::Coins
+                    {
+                        format!("{:.2}% to {:.2}%", lower * 100.0, upper * 100.0)
+                    }
+                    else {
+                        format!("{:.2} to {:.2}", lower, upper)
+                    };
+
+                    window.set_label(&format!("Calculating... {}, best so far: row {}, column {}",
+                                              bounds, row + 1, col + 1));
+                }
+
+                ReportMessage::Statistics(statistics) => {
+                    let table = format_statistics(&statistics);
+                    info!("GUI: Received search statistics\n{}", table);
+
+                    if SHOW_STATISTICS
+                    {
+                        window.set_tooltip(&table);
+                        Tooltip::enable(true);
+                    }
+                }
+
+                ReportMessage::Deductions(found) => {
+                    info!("GUI: Received {} deductions", found.len());
+                    deductions = found;
+                }
+
+                ReportMessage::ForcedSquares(found) => {
+                    info!("GUI: Received {} squares forced by the human rules", found.len());
+                    forced_squares = found;
+                }
+
+                ReportMessage::SquareOutcomes(row, col, values) => {
+                    info!("GUI: Received outcome values for square ({}, {})", row, col);
+                    outcomes[row][col] = Some(values);
+                }
+
                 ReportMessage::SquareSymbols(sp) => {
                     info!("GUI: Received symbol probs for all squares");
                     symbol_probs = Some(sp);
+                    informative_square = most_informative_square(array_to_u64(&state), &sp, max_bomb_prob);
                     window.set_label("Calculating...");
 
                     for r in 0..5
@@ -1028,9 +1335,12 @@ pub fn gui() -> ()
                             }
 
                             square_buttons[r][c].set_tooltip(
-                                &format!("Bomb: {:.2}%\nOne: {:.2}%\nTwo: {:.2}%\nThree: {:.2}%",
+                                &format!("Bomb: {:.2}%\nOne: {:.2}%\nTwo: {:.2}%\nThree: {:.2}%\nInformation: {:.2} bits{}{}",
                                          sp[r][c][0] * 100.0, sp[r][c][1] * 100.0,
                                          sp[r][c][2] * 100.0, sp[r][c][3] * 100.0,
+                                         symbol_entropy(&sp[r][c]),
+                                         information_tooltip(informative_square, max_bomb_prob, r, c),
+                                         logic_tooltip(&deductions, &forced_squares, r, c),
                                 )
                             );
                             Tooltip::enable(true);
@@ -1085,7 +1395,7 @@ pub fn gui() -> ()
 
                     info!("GUI: Square ({}, {}) was updated to {}", r, c, state[r][c]);
 
-                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread);
+                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
                     tell_thread_state(&to_thread, &state);
                     tell_thread_start(&to_thread);
 
@@ -1109,7 +1419,7 @@ pub fn gui() -> ()
 
                     info!("GUI: Level was updated to {}", level);
 
-                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread);
+                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
                     tell_thread_constraints(&to_thread, &sr, &sc, &br, &bc, level);
                     tell_thread_start(&to_thread);
                     window.set_label(TITLE_CALCULATING_POSSIBLE_BOARDS);
@@ -1127,7 +1437,7 @@ pub fn gui() -> ()
                         }
                     }
 
-                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread);
+                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
                     tell_thread_state(&to_thread, &state);
                     tell_thread_start(&to_thread);
 
@@ -1149,7 +1459,7 @@ pub fn gui() -> ()
 
                     info!("GUI: Sum of points of row {} updated to {}", r, sr[r]);
 
-                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread);
+                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
                     tell_thread_constraints(&to_thread, &sr, &sc, &br, &bc, level);
                     tell_thread_start(&to_thread);
                     window.set_label(TITLE_CALCULATING_POSSIBLE_BOARDS);
@@ -1170,7 +1480,7 @@ pub fn gui() -> ()
 
                     info!("GUI: Sum of points of col {} updated to {}", c, sc[c]);
 
-                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread);
+                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
                     tell_thread_constraints(&to_thread, &sr, &sc, &br, &bc, level);
                     tell_thread_start(&to_thread);
                     window.set_label(TITLE_CALCULATING_POSSIBLE_BOARDS);
@@ -1194,7 +1504,7 @@ pub fn gui() -> ()
 
                     info!("GUI: Bomb count of row {} updated to {}", r, br[r]);
 
-                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread);
+                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
                     tell_thread_constraints(&to_thread, &sr, &sc, &br, &bc, level);
                     tell_thread_start(&to_thread);
                     window.set_label(TITLE_CALCULATING_POSSIBLE_BOARDS);
@@ -1218,7 +1528,7 @@ pub fn gui() -> ()
 
                     info!("GUI: Bomb count of col {} updated to {}", c, bc[c]);
 
-                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread);
+                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
                     tell_thread_constraints(&to_thread, &sr, &sc, &br, &bc, level);
                     tell_thread_start(&to_thread);
                     window.set_label(TITLE_CALCULATING_POSSIBLE_BOARDS);
@@ -1244,7 +1554,7 @@ pub fn gui() -> ()
                     });
                     mode = m;
 
-                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread);
+                    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
                     tell_thread_mode(&to_thread, mode);
                     tell_thread_start(&to_thread);
                 }
@@ -1258,16 +1568,50 @@ pub fn gui() -> ()
 
                         info!("GUI: Set number of threads to {}", threads);
 
-                        tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread);
+                        tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
                         tell_thread_threads(&to_thread, threads);
                         tell_thread_start(&to_thread);
                     }
                 }
+
+                CacheSize => {
+                    let v = cache_counter.value() as usize;
+
+                    if cache_mb != v
+                    {
+                        cache_mb = v;
+
+                        info!("GUI: Set cache size to {} MB", cache_mb);
+
+                        tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
+                        tell_thread_cache_size(&to_thread, cache_mb);
+                        tell_thread_start(&to_thread);
+                    }
+                }
+
+                MaxBombChance => {
+                    let v = bomb_counter.value() / 100.0;
+
+                    if max_bomb_prob != v
+                    {
+                        max_bomb_prob = v;
+
+                        info!("GUI: Most informative square may be a bomb with at most {:.0}%", max_bomb_prob * 100.0);
+
+                        // the squares get redrawn from scratch, restarting sends everything again (mostly from the cache)
+                        tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
+                        tell_thread_start(&to_thread);
+                    }
+                }
             }
 
             Tooltip::disable();
             symbol_probs = None;
             probs = [[None;5];5];
+            outcomes = [[None;5];5];
+            deductions = Vec::new();
+            forced_squares = Vec::new();
+            informative_square = None;
 
             for r in 0..5
             {
@@ -1297,4 +1641,9 @@ pub fn gui() -> ()
             );
         }
     }
+
+    // a search might still be running, and the values of the last one might still be written to disk
+    tell_thread_to_stop_and_wait_till_it_is_stopped(&to_thread, &from_thread, &cancel);
+    tell_thread_to_exit(&to_thread);
+    search_thread.join().expect("Search thread panicked");
 }
\ No newline at end of file
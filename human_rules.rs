@@ -0,0 +1,611 @@
+use crate::csp_constraints::fsf_line;
+use crate::packed::get_from_packed_state;
+
+
+// candidate sets: bit s is set if the square could still be symbol s (0 = Voltorb)
+const ALL_SYMBOLS: u8 = 0b1111;
+const VOLTORB: u8 = 0b0001;
+const TWO_THREE: u8 = 0b1100;
+const THREE: u8 = 0b1000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Line
+{
+    Row(usize),
+    Col(usize),
+}
+
+// the small set of rules a human uses, in the order they're tried
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rule
+{
+    NoVoltorbs(Line), // line with 0 Voltorbs is all safe
+    AllVoltorbsFound(Line), // every Voltorb of the line is known, the rest is safe
+    VoltorbsFillLine(Line), // as many Voltorbs left as squares that can hold one
+    OnlyOnesLeft(Line), // points left = squares left - Voltorbs left, so no 2 or 3
+    OnlyThreesLeft(Line), // points left = 3 * (squares left - Voltorbs left), so all 3s
+    LineCompletion(Line), // fsf can't complete the line with that symbol on that square
+    Intersection(usize, usize), // row and column facts together decide the square
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProofStep
+{
+    pub rule: Rule,
+    pub eliminated: Vec<(usize, usize, u8)>, // square and the symbols it can't be anymore
+    pub premises: Vec<usize>, // earlier steps this one relies on, in a ForcedSquare by their place in its proof
+}
+
+// a square the rules decide, either a single symbol or at least not a Voltorb
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForcedSquare
+{
+    pub row: usize,
+    pub col: usize,
+    pub candidates: u8, // what's left for that square
+    pub proof: Vec<ProofStep>, // in the order they have to be read
+}
+
+
+// derive every square the human rules can force, each with an ordered proof,
+// None if the rules run into a contradiction (inconsistent constraints)
+pub fn explain_forced_squares(
+    org_packed_state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+) -> Option<Vec<ForcedSquare>>
+{
+    let mut candidates = [[ALL_SYMBOLS;5];5];
+    for row in 0..5
+    {
+        for col in 0..5
+        {
+            let symbol = get_from_packed_state(org_packed_state, row, col);
+            if symbol != 0
+            {
+                candidates[row][col] = 1 << symbol;
+            }
+        }
+    }
+
+    let mut steps: Vec<ProofStep> = Vec::new();
+    // steps which narrowed down that square
+    let mut narrowed_by: Vec<Vec<Vec<usize>>> = vec![vec![Vec::new(); 5]; 5];
+
+    loop
+    {
+        let mut progress = false;
+
+        // cheap named rules first, for every line
+        for line in all_lines()
+        {
+            for rule_index in 0..5
+            {
+                let squares = squares_of_line(line);
+                let (sum, bombs) = constraints_of_line(line, &sr, &sc, &br, &bc);
+                let cands: Vec<u8> = squares.iter().map(|&(r, c)| candidates[r][c]).collect();
+
+                let (rule, remove) = match simple_rule(rule_index, line, &cands, sum, bombs)
+                {
+                    Some(r) => r,
+                    None => continue,
+                };
+
+                let eliminated: Vec<(usize, usize, u8)> = squares
+                    .iter()
+                    .zip(remove.iter())
+                    .filter(|&(&(r, c), &m)| candidates[r][c] & m != 0)
+                    .map(|(&(r, c), &m)| (r, c, candidates[r][c] & m))
+                    .collect();
+
+                if eliminated.is_empty()
+                {
+                    continue;
+                }
+
+                // no premises when only the constraints of the line are used
+                let premises = match rule
+                {
+                    Rule::NoVoltorbs(_) => Vec::new(),
+                    _ => premises_of_line(&squares, &narrowed_by),
+                };
+
+                if !apply_step(rule, eliminated, premises, &mut candidates, &mut steps, &mut narrowed_by)
+                {
+                    return None;
+                }
+                progress = true;
+            }
+        }
+
+        if progress
+        {
+            continue;
+        }
+
+        // then try every candidate symbol and see whether fsf can still complete the line
+        for line in all_lines()
+        {
+            let squares = squares_of_line(line);
+            let (sum, bombs) = constraints_of_line(line, &sr, &sc, &br, &bc);
+            let cands: Vec<u8> = squares.iter().map(|&(r, c)| candidates[r][c]).collect();
+
+            let mut eliminated = Vec::new();
+            for i in 0..5
+            {
+                let (r, c) = squares[i];
+                let mut impossible = 0;
+                for symbol in 0..4
+                {
+                    if cands[i] & (1 << symbol) != 0 && !can_complete_line(&cands, i, symbol, sum, bombs)
+                    {
+                        impossible |= 1 << symbol;
+                    }
+                }
+
+                if impossible != 0
+                {
+                    eliminated.push((r, c, impossible));
+                }
+            }
+
+            if eliminated.is_empty()
+            {
+                continue;
+            }
+
+            let premises = premises_of_line(&squares, &narrowed_by);
+            if !apply_step(Rule::LineCompletion(line), eliminated, premises, &mut candidates, &mut steps, &mut narrowed_by)
+            {
+                return None;
+            }
+            progress = true;
+            break; // back to the simple rules, they read nicer
+        }
+
+        if !progress
+        {
+            break;
+        }
+    }
+
+    let mut forced = Vec::new();
+    for row in 0..5
+    {
+        for col in 0..5
+        {
+            if get_from_packed_state(org_packed_state, row, col) != 0 || !is_forced(candidates[row][col])
+            {
+                continue;
+            }
+
+            forced.push(ForcedSquare {
+                row,
+                col,
+                candidates: candidates[row][col],
+                proof: collect_proof(&narrowed_by[row][col], &steps),
+            });
+        }
+    }
+
+    Some(forced)
+}
+
+
+// a single symbol, or at least not a Voltorb
+fn is_forced(candidates: u8) -> bool
+{
+    candidates.count_ones() == 1 || candidates & VOLTORB == 0
+}
+
+
+fn all_lines() -> Vec<Line>
+{
+    (0..5).map(Line::Row).chain((0..5).map(Line::Col)).collect()
+}
+
+
+fn squares_of_line(line: Line) -> [(usize, usize);5]
+{
+    let mut squares = [(0, 0);5];
+    for i in 0..5
+    {
+        squares[i] = match line
+        {
+            Line::Row(r) => (r, i),
+            Line::Col(c) => (i, c),
+        };
+    }
+    squares
+}
+
+
+fn constraints_of_line(
+    line: Line,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+) -> (usize, usize)
+{
+    match line
+    {
+        Line::Row(r) => (sr[r], br[r]),
+        Line::Col(c) => (sc[c], bc[c]),
+    }
+}
+
+
+// rule number rule_index applied to one line, returns the rule and
+// which symbols to remove from each square of the line if it fires
+fn simple_rule(
+    rule_index: usize,
+    line: Line,
+    cands: &Vec<u8>,
+    sum: usize,
+    bombs: usize,
+) -> Option<(Rule, [u8;5])>
+{
+    let known_bombs = cands.iter().filter(|&&m| m == VOLTORB).count();
+    let unknown: Vec<bool> = cands.iter().map(|&m| m.count_ones() > 1).collect();
+    let nr_unknown = unknown.iter().filter(|&&u| u).count();
+    let known_points: usize = cands
+        .iter()
+        .filter(|&&m| m.count_ones() == 1 && m != VOLTORB)
+        .map(|&m| m.trailing_zeros() as usize)
+        .sum();
+
+    if known_bombs > bombs || known_points > sum
+    {
+        return None; // contradiction, LineCompletion will find it
+    }
+
+    let bombs_left = bombs - known_bombs;
+    let points_left = sum - known_points;
+
+    let mut remove = [0;5];
+
+    let rule = match rule_index
+    {
+        0 => {
+            if bombs != 0
+            {
+                return None;
+            }
+            remove = [VOLTORB;5];
+            Rule::NoVoltorbs(line)
+        }
+
+        1 => {
+            if bombs == 0 || bombs_left != 0
+            {
+                return None;
+            }
+            for i in 0..5
+            {
+                if unknown[i]
+                {
+                    remove[i] = VOLTORB;
+                }
+            }
+            Rule::AllVoltorbsFound(line)
+        }
+
+        2 => {
+            let could_be_bomb: Vec<bool> = (0..5)
+                .map(|i| unknown[i] && cands[i] & VOLTORB != 0)
+                .collect();
+            if bombs_left == 0 || could_be_bomb.iter().filter(|&&b| b).count() != bombs_left
+            {
+                return None;
+            }
+            for i in 0..5
+            {
+                if could_be_bomb[i]
+                {
+                    remove[i] = ALL_SYMBOLS & !VOLTORB;
+                }
+            }
+            Rule::VoltorbsFillLine(line)
+        }
+
+        3 | 4 => {
+            if nr_unknown <= bombs_left
+            {
+                return None;
+            }
+            let numbers_left = nr_unknown - bombs_left;
+
+            if rule_index == 3 && points_left == numbers_left
+            {
+                for i in 0..5
+                {
+                    if unknown[i]
+                    {
+                        remove[i] = TWO_THREE;
+                    }
+                }
+                Rule::OnlyOnesLeft(line)
+            }
+            else if rule_index == 4 && points_left == 3 * numbers_left
+            {
+                for i in 0..5
+                {
+                    if unknown[i]
+                    {
+                        remove[i] = ALL_SYMBOLS & !VOLTORB & !THREE;
+                    }
+                }
+                Rule::OnlyThreesLeft(line)
+            }
+            else {
+                return None;
+            }
+        }
+
+        _ => return None,
+    };
+
+    Some((rule, remove))
+}
+
+
+// is there any way to pick one candidate per square, with symbol on square index,
+// so that fsf accepts the completed line?
+fn can_complete_line(
+    cands: &Vec<u8>,
+    index: usize,
+    symbol: usize,
+    sum: usize,
+    bombs: usize,
+) -> bool
+{
+    let mut line = [127;5];
+    line[index] = symbol;
+    complete_line(cands, &mut line, 0, sum, bombs)
+}
+
+
+fn complete_line(
+    cands: &Vec<u8>,
+    line: &mut [usize;5],
+    i: usize,
+    sum: usize,
+    bombs: usize,
+) -> bool
+{
+    if !fsf_line(line, sum, bombs)
+    {
+        return false;
+    }
+
+    if i == 5
+    {
+        return true;
+    }
+
+    if line[i] <= 3 // already set
+    {
+        return complete_line(cands, line, i + 1, sum, bombs);
+    }
+
+    for symbol in 0..4
+    {
+        if cands[i] & (1 << symbol) != 0
+        {
+            line[i] = symbol;
+            if complete_line(cands, line, i + 1, sum, bombs)
+            {
+                line[i] = 127;
+                return true;
+            }
+        }
+    }
+    line[i] = 127;
+
+    false
+}
+
+
+// every step that narrowed down a square of that line so far
+fn premises_of_line(
+    squares: &[(usize, usize);5],
+    narrowed_by: &Vec<Vec<Vec<usize>>>,
+) -> Vec<usize>
+{
+    let mut premises: Vec<usize> = squares
+        .iter()
+        .flat_map(|&(r, c)| narrowed_by[r][c].iter().copied())
+        .collect();
+    premises.sort_unstable();
+    premises.dedup();
+    premises
+}
+
+
+// record the step, false if a square has no candidate left
+fn apply_step(
+    rule: Rule,
+    eliminated: Vec<(usize, usize, u8)>,
+    premises: Vec<usize>,
+    candidates: &mut [[u8;5];5],
+    steps: &mut Vec<ProofStep>,
+    narrowed_by: &mut Vec<Vec<Vec<usize>>>,
+) -> bool
+{
+    let index = steps.len();
+    let mut newly_forced = Vec::new();
+
+    for &(r, c, m) in &eliminated
+    {
+        let was_forced = is_forced(candidates[r][c]);
+        candidates[r][c] &= !m;
+        narrowed_by[r][c].push(index);
+
+        if candidates[r][c] == 0
+        {
+            return false;
+        }
+
+        if !was_forced && is_forced(candidates[r][c])
+        {
+            newly_forced.push((r, c));
+        }
+    }
+
+    steps.push(ProofStep {
+        rule,
+        eliminated,
+        premises,
+    });
+
+    // name it when the row and the column were both needed
+    for (r, c) in newly_forced
+    {
+        let lines: Vec<Line> = narrowed_by[r][c]
+            .iter()
+            .filter_map(|&s| line_of_rule(steps[s].rule))
+            .collect();
+
+        let from_row = lines.contains(&Line::Row(r));
+        let from_col = lines.contains(&Line::Col(c));
+
+        if from_row && from_col
+        {
+            let premises = narrowed_by[r][c].clone();
+            narrowed_by[r][c].push(steps.len());
+            steps.push(ProofStep {
+                rule: Rule::Intersection(r, c),
+                eliminated: Vec::new(),
+                premises,
+            });
+        }
+    }
+
+    true
+}
+
+
+fn line_of_rule(rule: Rule) -> Option<Line>
+{
+    match rule
+    {
+        Rule::NoVoltorbs(l) |
+        Rule::AllVoltorbsFound(l) |
+        Rule::VoltorbsFillLine(l) |
+        Rule::OnlyOnesLeft(l) |
+        Rule::OnlyThreesLeft(l) |
+        Rule::LineCompletion(l) => Some(l),
+        Rule::Intersection(..) => None,
+    }
+}
+
+
+// the steps that narrowed the square plus everything they rely on, in order,
+// with the premises renumbered to their place in that proof
+fn collect_proof(
+    start: &Vec<usize>,
+    steps: &Vec<ProofStep>,
+) -> Vec<ProofStep>
+{
+    let mut needed = vec![false; steps.len()];
+    let mut todo = start.clone();
+
+    while let Some(s) = todo.pop()
+    {
+        if !needed[s]
+        {
+            needed[s] = true;
+            todo.extend(steps[s].premises.iter().copied());
+        }
+    }
+
+    let order: Vec<usize> = (0..steps.len()).filter(|&s| needed[s]).collect();
+
+    order
+        .iter()
+        .map(|&s| {
+            let mut step = steps[s].clone();
+            step.premises = step.premises
+                .iter()
+                .map(|p| order.binary_search(p).expect("Premises are part of the proof"))
+                .collect();
+            step
+        })
+        .collect()
+}
+
+
+// numbered, human-readable proof e.g. for a tooltip or a stream overlay,
+// rows and columns counted from 1 like in the GUI
+pub fn describe_forced_square(forced: &ForcedSquare) -> Vec<String>
+{
+    let mut lines = vec![format!("({}, {}) is {}", forced.row + 1, forced.col + 1, describe_candidates(forced.candidates))];
+
+    for (i, step) in forced.proof.iter().enumerate()
+    {
+        let line_name = |l: Line| match l
+        {
+            Line::Row(r) => format!("Row {}", r + 1),
+            Line::Col(c) => format!("Column {}", c + 1),
+        };
+
+        let text = match step.rule
+        {
+            Rule::NoVoltorbs(l) => format!("{} has 0 Voltorbs, so all of it is safe", line_name(l)),
+            Rule::AllVoltorbsFound(l) => format!("{} has all of its Voltorbs found, so the rest is safe", line_name(l)),
+            Rule::VoltorbsFillLine(l) => format!("{} has as many Voltorbs left as squares that can hold one", line_name(l)),
+            Rule::OnlyOnesLeft(l) => format!("{} has points left = squares left - Voltorbs left, so no 2s or 3s", line_name(l)),
+            Rule::OnlyThreesLeft(l) => format!("{} has points left = 3 * (squares left - Voltorbs left), so all 3s", line_name(l)),
+            Rule::LineCompletion(l) => format!("{} can't be completed otherwise", line_name(l)),
+            Rule::Intersection(r, c) => format!("Row {} and column {} together decide ({}, {})", r + 1, c + 1, r + 1, c + 1),
+        };
+
+        let eliminated: Vec<String> = step.eliminated
+            .iter()
+            .map(|&(r, c, m)| format!("({}, {}) isn't {}", r + 1, c + 1, describe_symbols(m)))
+            .collect();
+
+        let mut line = format!("{}. {}", i + 1, text);
+
+        if !eliminated.is_empty()
+        {
+            line.push_str(&format!(": {}", eliminated.join(", ")));
+        }
+
+        if !step.premises.is_empty()
+        {
+            let premises: Vec<String> = step.premises.iter().map(|p| (p + 1).to_string()).collect();
+            line.push_str(&format!(" (from {})", premises.join(", ")));
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+
+fn describe_candidates(candidates: u8) -> String
+{
+    if candidates.count_ones() == 1
+    {
+        format!("a {}", describe_symbols(candidates))
+    }
+    else {
+        format!("safe (a {})", describe_symbols(candidates))
+    }
+}
+
+
+fn describe_symbols(m: u8) -> String
+{
+    let names = ["Voltorb", "1", "2", "3"];
+    let symbols: Vec<&str> = (0..4)
+        .filter(|&s| m & (1 << s) != 0)
+        .map(|s| names[s])
+        .collect();
+    symbols.join("/")
+}
@@ -0,0 +1,196 @@
+use crate::csp_constraints::find_possible_boards;
+use crate::possible_boards::{accumulate_symbol_weights, boards_by_depth};
+use crate::packed::{get_from_packed_state, set_in_packed_state, board_has_possible_2_3_for_state};
+use crate::parsing::examples_357;
+use crate::search::SearchResult;
+
+// the most informative square may be a bomb with at most that chance, unless set otherwise
+pub const DEFAULT_MAX_BOMB_PROB: f64 = 0.3;
+
+
+// What flipping each square tells about the hidden board, for learning how to narrow it down.
+// The board decides the symbol of a square, so the expected reduction of the entropy of the
+// (weighted) possible boards is just the entropy of that square's symbol
+#[derive(Copy, Clone, Debug)]
+pub struct InformationReport
+{
+    pub square: Option<(usize, usize)>, // most bits among the squares safe enough, None if no square is
+    pub bits: [[Option<f64>;5];5], // expected bits gained by flipping each face-down square
+    pub symbol_probs: [[[f64;4];5];5], // chances to be a bomb/1/2/3
+    pub board_bits: f64, // entropy of the possible boards, 0 once the board is known
+}
+
+
+// Err(InconsistentPuzzle) if there are no possible boards
+pub fn information_report(
+    packed_state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    max_bomb_prob: f64,
+) -> Result<InformationReport, SearchResult>
+{
+    let (o_pb, o_count, weights) = find_possible_boards(packed_state, &sr, &sc, &br, &bc, level);
+
+    if o_pb.is_empty()
+    {
+        return Err(SearchResult::InconsistentPuzzle);
+    }
+
+    let (possible_boards, indices) = boards_by_depth(&o_pb, &o_count, &weights);
+    let acc = accumulate_symbol_weights(packed_state, &possible_boards, &indices, 0, &weights);
+
+    let mut bits = [[None;5];5];
+
+    for row in 0..5
+    {
+        for col in 0..5
+        {
+            if get_from_packed_state(packed_state, row, col) == 0
+            {
+                bits[row][col] = Some(symbol_entropy(&acc[row][col]));
+            }
+        }
+    }
+
+    Ok(InformationReport {
+        square: most_informative_square(packed_state, &acc, max_bomb_prob),
+        bits,
+        symbol_probs: acc,
+        board_bits: board_entropy(&o_count, &weights),
+    })
+}
+
+
+// face-down square with the most bits among those with at most max_bomb_prob to be a bomb,
+// None if no square is that safe or there's nothing left to learn
+pub fn most_informative_square(
+    packed_state: u64,
+    acc: &[[[f64;4];5];5],
+    max_bomb_prob: f64,
+) -> Option<(usize, usize)>
+{
+    let mut best: Option<(f64, f64, (usize, usize))> = None; // bits, bomb prob, square
+
+    for row in 0..5
+    {
+        for col in 0..5
+        {
+            if get_from_packed_state(packed_state, row, col) != 0
+            {
+                continue;
+            }
+
+            let b = symbol_entropy(&acc[row][col]);
+
+            // nothing to learn from a square that's known already
+            if acc[row][col][0] > max_bomb_prob || b < 1e-12
+            {
+                continue;
+            }
+
+            // equally informative squares: the safer one
+            let better = match best
+            {
+                None => true,
+                Some((best_bits, best_bomb, _)) => b > best_bits + 1e-12 ||
+                    (b > best_bits - 1e-12 && acc[row][col][0] < best_bomb),
+            };
+
+            if better
+            {
+                best = Some((b, acc[row][col][0], (row, col)));
+            }
+        }
+    }
+
+    best.map(|(_, _, square)| square)
+}
+
+
+// in bits, of the symbol of a square with these chances to be a bomb/1/2/3
+pub fn symbol_entropy(probs: &[f64;4]) -> f64
+{
+    probs
+        .iter()
+        .filter(|&&p| p > 0.0)
+        .fold(0.0, |h, &p| h - p * p.log2())
+}
+
+
+// in bits, of the possible boards with the weights of their level
+fn board_entropy(o_count: &Vec<usize>, weights: &Vec<f64>) -> f64
+{
+    let total: f64 = o_count.iter().zip(weights).map(|(&count, &w)| count as f64 * w).sum();
+
+    o_count
+        .iter()
+        .zip(weights)
+        .filter(|&(&count, &w)| count > 0 && w > 0.0)
+        .fold(0.0, |h, (&count, &w)| {
+            let p = w / total;
+            h - count as f64 * p * p.log2()
+        })
+}
+
+
+// plays every example by flipping the squares that are a 2 or 3 for sure and else the most informative
+// square with at most max_bomb_prob to be a bomb, stops when no square is that safe. Prints how the boards left shrink
+pub fn information_game_report(max_bomb_prob: f64)
+{
+    let mut won = 0;
+    let mut lost = 0;
+    let mut stuck = 0;
+
+    for (nr, sr, sc, br, bc, level, board) in examples_357()
+    {
+        let mut state = 0;
+        let mut flips = Vec::new();
+
+        let outcome = loop
+        {
+            // every 2 and 3 of the hidden board is face up
+            if !board_has_possible_2_3_for_state(board, state)
+            {
+                won += 1;
+                break "won";
+            }
+
+            let report = information_report(state, &sr, &sc, &br, &bc, level, max_bomb_prob)
+                .expect("The hidden board is always possible");
+
+            let sure_coins = (0..25)
+                .map(|square| (square / 5, square % 5))
+                .find(|&(row, col)| get_from_packed_state(state, row, col) == 0 &&
+                    (report.symbol_probs[row][col][2] > 1.0 - 1e-12 || report.symbol_probs[row][col][3] > 1.0 - 1e-12));
+
+            let (row, col) = match sure_coins.or(report.square)
+            {
+                Some(square) => square,
+                None => {
+                    stuck += 1;
+                    break "stuck";
+                }
+            };
+
+            let symbol = get_from_packed_state(board, row, col);
+            flips.push(format!("({}, {}) {:.2} of {:.2} bits", row, col,
+                               report.bits[row][col].unwrap_or(0.0), report.board_bits));
+
+            if symbol == 0
+            {
+                lost += 1;
+                break "lost";
+            }
+
+            state = set_in_packed_state(state, row, col, symbol);
+        };
+
+        println!("Puzzle {}: {} after {}", nr, outcome, flips.join(", "));
+    }
+
+    println!("At most {:.0}% bomb chance: won {}, lost {}, stuck {}",
+             max_bomb_prob * 100.0, won, lost, stuck);
+}
@@ -17,13 +17,31 @@ mod parsing;
 mod benchmark;
 mod constraints;
 mod validate_symbol_probs;
+mod policy;
+mod regret;
+mod deductions;
+mod human_rules;
+mod disk_cache;
+mod transposition;
+mod validate_cache;
+mod validate_deductions;
+mod symmetry;
+mod stats;
+mod board_index;
+mod board_session;
+mod scratch;
+mod cancel;
+mod approx;
+mod depth_limited;
+mod sampling;
+mod information;
 
 use crate::gui::gui;
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::path::Path;
 use crate::search::compute_win_chance_exact;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::io::{BufWriter, Write};
 use std::collections::HashMap;
 use crate::packed::{array_to_u64, u64_to_array, set_in_packed_state};
@@ -33,10 +51,17 @@ use crate::csp_valid::{count_valid_dumb, count_valid_smart};
 use rayon::prelude::*;
 use crate::csp_constraints::find_possible_boards;
 use crate::possible_boards::accumulate_symbol_weights;
-use crate::Mode::{BenchmarkNoGUI, GUI, ValidateNoGUI};
-use crate::benchmark::benchmark;
+use crate::Mode::{BenchmarkNoGUI, GUI, ValidateNoGUI, PolicyNoGUI, PolicyFileNoGUI, RegretNoGUI, CacheBenchmarkNoGUI, ValidateCacheNoGUI, EnumerationBenchmarkNoGUI, BoundsNoGUI, BudgetedNoGUI, BudgetedPositionNoGUI, DepthLimitedNoGUI, SampledNoGUI, InformationNoGUI, NodesBenchmarkNoGUI, ThreadsBenchmarkNoGUI, StatisticsNoGUI, ValidateDeductionsNoGUI, ValidateHumanRulesNoGUI};
+use crate::benchmark::{benchmark, benchmark_cache, benchmark_enumeration, benchmark_bounds, benchmark_budgeted, benchmark_depth_limited, benchmark_sampled, benchmark_nodes, benchmark_threads, benchmark_statistics};
 use env_logger::{fmt::Color, Env, Builder};
 use crate::validate_symbol_probs::validate;
+use crate::validate_cache::validate_cache;
+use crate::validate_deductions::{validate_deductions, validate_human_rules};
+use crate::policy::{compare_with_optimum, policy_file_report};
+use crate::regret::regret_report;
+use crate::information::{information_game_report, DEFAULT_MAX_BOMB_PROB};
+use crate::approx::{budgeted_report, Budget};
+use crate::gui::SearchMode;
 
 
 enum Mode
@@ -44,6 +69,23 @@ enum Mode
     GUI,
     BenchmarkNoGUI,
     ValidateNoGUI,
+    PolicyNoGUI,
+    PolicyFileNoGUI,
+    RegretNoGUI,
+    CacheBenchmarkNoGUI,
+    ValidateCacheNoGUI,
+    EnumerationBenchmarkNoGUI,
+    BoundsNoGUI,
+    BudgetedNoGUI,
+    BudgetedPositionNoGUI,
+    DepthLimitedNoGUI,
+    SampledNoGUI,
+    InformationNoGUI,
+    NodesBenchmarkNoGUI,
+    ThreadsBenchmarkNoGUI,
+    StatisticsNoGUI,
+    ValidateDeductionsNoGUI,
+    ValidateHumanRulesNoGUI,
 }
 
 fn main() {
@@ -66,5 +108,22 @@ fn main() {
         GUI => gui(),
         BenchmarkNoGUI => benchmark(),
         ValidateNoGUI => validate(),
+        PolicyNoGUI => compare_with_optimum(),
+        PolicyFileNoGUI => policy_file_report("game.txt", "policy.txt"),
+        RegretNoGUI => regret_report("game.txt"),
+        CacheBenchmarkNoGUI => benchmark_cache(),
+        ValidateCacheNoGUI => validate_cache(),
+        EnumerationBenchmarkNoGUI => benchmark_enumeration(),
+        BoundsNoGUI => benchmark_bounds(),
+        BudgetedNoGUI => benchmark_budgeted(),
+        BudgetedPositionNoGUI => budgeted_report("game.txt", SearchMode::WinChance, Budget::Time(Duration::from_secs(1))),
+        DepthLimitedNoGUI => benchmark_depth_limited(),
+        SampledNoGUI => benchmark_sampled(),
+        InformationNoGUI => information_game_report(DEFAULT_MAX_BOMB_PROB),
+        NodesBenchmarkNoGUI => benchmark_nodes(),
+        ThreadsBenchmarkNoGUI => benchmark_threads(),
+        StatisticsNoGUI => benchmark_statistics(),
+        ValidateDeductionsNoGUI => validate_deductions(),
+        ValidateHumanRulesNoGUI => validate_human_rules(),
     };
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
 use std::path::Path;
+use std::fs;
 use std::io;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
@@ -89,4 +90,33 @@ fn string_to_puzzles(s: &str) -> Vec<(usize, [usize;5], [usize;5], [usize;5], [u
     }
 
     puzzles
+}
+
+// game file: the whole board and the level on the first line (as in examples.txt),
+// then one "<row> <col>" per flip in the order they were played. Blank lines are skipped
+pub fn read_game(path: &str) -> ((usize, [usize;5], [usize;5], [usize;5], [usize;5], usize, u64), Vec<(usize, usize)>)
+{
+    let content = fs::read_to_string(path)
+        .unwrap_or_else(|_| panic!("Failed to read game file {}", path));
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+
+    let puzzle = lines.next().expect("Game file is empty");
+
+    let mut moves = Vec::new();
+    for line in lines
+    {
+        let parts: Vec<usize> = line
+            .split_whitespace()
+            .map(|p| p.parse().unwrap_or_else(|_| panic!("Failed to parse move \"{}\"", line)))
+            .collect();
+
+        if parts.len() != 2
+        {
+            panic!("Move \"{}\" should be <row> <col>", line);
+        }
+
+        moves.push((parts[0], parts[1]));
+    }
+
+    (string_to_level_and_constraints(puzzle), moves)
 }
\ No newline at end of file
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::Instant;
+use crossbeam_channel::unbounded;
+use crate::transposition::{TranspositionTable, ReplacementPolicy, ValuePrecision};
+use crate::csp_constraints::find_possible_boards;
+use crate::possible_boards::{accumulate_symbol_weights, filter_possible_boards_of_next_depth, boards_by_depth};
+use crate::packed::{get_from_packed_state, set_in_packed_state, coins_of_state, array_to_u64};
+use crate::search::{compute_win_chance_exact, is_won_state, terminal_value, SearchResult};
+use crate::cancel::CancelToken;
+use crate::gui::SearchMode;
+use crate::parsing::{examples_357, read_game};
+
+
+// A strategy that picks (at most) one square in every state.
+// Its value is computed exactly over all weighted possible boards,
+// no sampling, so it can be compared to the optimum directly
+#[derive(Clone, Debug)]
+pub enum Policy
+{
+    SurviveNextMove, // lowest chance to be a bomb among the squares that could be a 2 or 3
+    MoveList(Vec<(usize, usize)>), // flip these squares in that order, skipping face-up ones
+    Table(HashMap<u64, (usize, usize)>), // packed state -> square, e.g. from a policy file
+}
+
+
+// exact win chance / expected coins (depending on the mode) of following the policy
+// from the given state, SuccessfulSearchWithInfo(value, seconds, evaluated states)
+pub fn evaluate_policy(
+    org_packed_state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    mode: SearchMode,
+    policy: &Policy,
+) -> SearchResult
+{
+    let start_of_computation = Instant::now();
+
+    let (o_pb, o_count, weights) = find_possible_boards(org_packed_state, &sr, &sc, &br, &bc, level);
+
+    if o_pb.is_empty()
+    {
+        return SearchResult::InconsistentPuzzle;
+    }
+
+    let (mut possible_boards, mut indices) = boards_by_depth(&o_pb, &o_count, &weights);
+
+    if is_won_state(org_packed_state, &possible_boards, &indices, 0, weights.len())
+    {
+        return SearchResult::TerminalState;
+    }
+
+    let mut nodes = 0;
+    let value = sh_policy(
+        0,
+        org_packed_state,
+        &mut possible_boards,
+        &mut indices,
+        0,
+        &weights,
+        level,
+        mode,
+        policy,
+        &mut nodes,
+    );
+
+    SearchResult::SuccessfulSearchWithInfo(value, start_of_computation.elapsed().as_secs_f64(), nodes)
+}
+
+
+// like sh_exact, but follows the single square of the policy instead of maximizing
+fn sh_policy(
+    depth: usize,
+    state: u64,
+    possible_boards: &mut Vec<u64>,
+    indices: &mut Vec<usize>,
+    index_start: usize,
+    weights: &Vec<f64>,
+    level: usize,
+    mode: SearchMode,
+    policy: &Policy,
+    nodes: &mut usize,
+) -> f64
+{
+    *nodes += 1;
+
+    let index_end = index_start + weights.len();
+
+    // the root has already been filtered
+    if depth > 0
+    {
+        filter_possible_boards_of_next_depth(
+            state,
+            possible_boards,
+            indices,
+            index_start - weights.len(),
+            weights,
+        );
+    }
+
+    // the root isn't won, but can still be terminal in the modes that count flips (as in sh_exact_root)
+    if let Some(v) = terminal_value(state, || is_won_state(state, possible_boards, &indices, index_start, index_end), level, mode)
+    {
+        return v;
+    }
+
+    let acc = accumulate_symbol_weights(
+        state, possible_boards, &indices, index_start, weights
+    );
+
+    let resign_value = {
+        if mode == SearchMode::Coins
+        {
+            coins_of_state(state) as f64
+        }
+        else {
+            0.0
+        }
+    };
+
+    let (row, col) = match choose_square(policy, state, &acc, mode)
+    {
+        Some(square) => square,
+        None => return resign_value,
+    };
+
+    let mut expected_value = 0.0;
+    for symbol in 1..4
+    {
+        if acc[row][col][symbol] == 0.0
+        {
+            continue;
+        }
+
+        let child = set_in_packed_state(state, row, col, symbol);
+        expected_value += acc[row][col][symbol] * sh_policy(
+            depth + 1,
+            child,
+            possible_boards,
+            indices,
+            index_end,
+            weights,
+            level,
+            mode,
+            policy,
+            nodes,
+        );
+    }
+
+    expected_value
+}
+
+
+// the square the policy flips in that state, None means it stops flipping
+fn choose_square(
+    policy: &Policy,
+    state: u64,
+    acc: &[[[f64;4];5];5],
+    mode: SearchMode,
+) -> Option<(usize, usize)>
+{
+    let square = match policy
+    {
+        Policy::SurviveNextMove => {
+            // 'useless' squares are only worth it when the number of flipped cards counts
+            let counting_mode = mode == SearchMode::WinEight ||
+                mode == SearchMode::SurviveLevel ||
+                mode == SearchMode::SurviveEight;
+
+            let mut best: Option<(usize, usize)> = None;
+            for row in 0..5
+            {
+                for col in 0..5
+                {
+                    if get_from_packed_state(state, row, col) != 0
+                    {
+                        continue;
+                    }
+
+                    if acc[row][col][2] > 0.0 || acc[row][col][3] > 0.0 || counting_mode
+                    {
+                        match best
+                        {
+                            Some((r, c)) if acc[r][c][0] <= acc[row][col][0] => (),
+                            _ => best = Some((row, col)),
+                        }
+                    }
+                }
+            }
+            best
+        }
+
+        Policy::MoveList(moves) => {
+            moves
+                .iter()
+                .find(|&&(row, col)| get_from_packed_state(state, row, col) == 0)
+                .copied()
+        }
+
+        Policy::Table(table) => table.get(&state).copied(),
+    };
+
+    if let Some((row, col)) = square
+    {
+        if get_from_packed_state(state, row, col) != 0
+        {
+            warn!("Policy picked the face-up square ({}, {}), treating it as stopping", row, col);
+            return None;
+        }
+    }
+
+    square
+}
+
+
+// policy file: one line per state, e.g. "00100-00000-00000-00000-00000 2 3"
+// means flip row 2, col 3 when only the square (0, 2) is face-up showing a 1
+pub fn load_policy_file(path: &str) -> Policy
+{
+    let content = fs::read_to_string(path)
+        .unwrap_or_else(|_| panic!("Failed to read policy file {}", path));
+
+    let mut table = HashMap::new();
+    for line in content.lines()
+    {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty()
+        {
+            continue;
+        }
+
+        if parts.len() != 3
+        {
+            panic!("Policy line \"{}\" should be <state> <row> <col>", line);
+        }
+
+        let digits: Vec<usize> = parts[0]
+            .chars()
+            .filter(|&c| c != '-')
+            .map(|c| c.to_digit(10).unwrap_or_else(|| panic!("Failed converting char {} to integer", c)) as usize)
+            .collect();
+
+        if digits.len() != 25
+        {
+            panic!("Policy state \"{}\" doesn't have 25 squares", parts[0]);
+        }
+
+        let mut state = [[0; 5]; 5];
+        for i in 0..25
+        {
+            state[i / 5][i % 5] = digits[i];
+        }
+
+        let row = parts[1].parse().expect("Failed to parse row of policy line");
+        let col = parts[2].parse().expect("Failed to parse col of policy line");
+
+        table.insert(array_to_u64(&state), (row, col));
+    }
+
+    Policy::Table(table)
+}
+
+
+// how much worse is the SurviveNextMove heuristic than the optimum? No sampling noise
+pub fn compare_with_optimum()
+{
+    let threads = 12;
+    let mode = SearchMode::WinChance;
+    let policy = Policy::SurviveNextMove;
+
+    let cache = TranspositionTable::new(256, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+    let mut total_optimal = 0.0;
+    let mut total_policy = 0.0;
+
+    for (nr, sr, sc, br, bc, level, _state) in examples_357()
+    {
+        let cancel = CancelToken::new();
+        let (result_reporting_sender, _result_reporting_receiver) = unbounded();
+
+        cache.clear();
+
+        let optimal = compute_win_chance_exact(
+            0, &sr, &sc, &br, &bc, level,
+            &cache,
+            &cancel,
+            &result_reporting_sender,
+            mode, threads);
+
+        let evaluated = evaluate_policy(0, &sr, &sc, &br, &bc, level, mode, &policy);
+
+        if let (SearchResult::SuccessfulSearchWithInfo(opt, _, _), SearchResult::SuccessfulSearchWithInfo(val, _, nodes)) = (optimal, evaluated)
+        {
+            println!("Puzzle {}: optimal {:.4}, {:?} {:.4}, loss {:.4} ({} states)",
+                     nr, opt, policy, val, opt - val, nodes);
+            total_optimal += opt;
+            total_policy += val;
+        }
+    }
+
+    println!("{}", "-".repeat(80));
+    println!("Sum optimal: {:.4}, sum {:?}: {:.4}", total_optimal, policy, total_policy);
+}
+
+
+// how much worse than the optimum is the policy of a policy file on the puzzle of a game file?
+// Only the first line of the game file (see read_game) is used, the policy starts from the empty board
+pub fn policy_file_report(game_path: &str, policy_path: &str)
+{
+    let threads = 12;
+    let mode = SearchMode::WinChance;
+    let policy = load_policy_file(policy_path);
+
+    let ((_, sr, sc, br, bc, level, _board), _) = read_game(game_path);
+
+    let cache = TranspositionTable::new(256, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+    let cancel = CancelToken::new();
+    let (result_reporting_sender, _result_reporting_receiver) = unbounded();
+
+    let optimal = compute_win_chance_exact(
+        0, &sr, &sc, &br, &bc, level,
+        &cache,
+        &cancel,
+        &result_reporting_sender,
+        mode, threads);
+
+    let evaluated = evaluate_policy(0, &sr, &sc, &br, &bc, level, mode, &policy);
+
+    match (optimal, evaluated)
+    {
+        (SearchResult::SuccessfulSearchWithInfo(opt, _, _), SearchResult::SuccessfulSearchWithInfo(val, _, nodes)) => {
+            println!("Optimal {:.4}, policy file {} {:.4}, loss {:.4} ({} states)",
+                     opt, policy_path, val, opt - val, nodes);
+        }
+        (optimal, evaluated) => println!("Optimal {:?}, policy file {} {:?}", optimal, policy_path, evaluated),
+    }
+}
@@ -55,6 +55,40 @@ pub fn accumulate_symbol_weights(
     acc
 }
 
+// one big array for all depths and weights,
+// an list of indices marks the start of a depth-weight group,
+// including a pointer at the end
+pub fn boards_by_depth(
+    o_pb: &Vec<u64>,
+    o_count: &Vec<usize>,
+    weights: &Vec<f64>,
+) -> (Vec<u64>, Vec<usize>)
+{
+    let mut possible_boards = Vec::with_capacity(1024 * 1024);
+    let mut indices = vec![0; weights.len() * 26 + 1];
+
+    // put in all the boards
+    for i in 0..o_pb.len()
+    {
+        possible_boards.push(o_pb[i]);
+    }
+
+    // set the indices
+    let mut index = 0;
+
+    // depth 0 starts at index 0
+    indices[0] = 0;
+    // add the start indices of each weight group after that
+    // including the first empty index
+    for j in 0..o_count.len()
+    {
+        index += o_count[j];
+        indices[j + 1] = index;
+    }
+
+    (possible_boards, indices)
+}
+
 // removes all states without state[row][col] == symbol
 pub fn filter_possible_boards_of_next_depth(
     packed_state: u64,
@@ -83,4 +117,4 @@ pub fn filter_possible_boards_of_next_depth(
         // Or the end of the boards of that depth, anyway set the index correctly:
         indices[index_start+weights.len()+index_weight+1] = possible_boards.len();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,231 @@
+use crossbeam_channel::unbounded;
+use crate::transposition::{TranspositionTable, ReplacementPolicy, ValuePrecision};
+use crate::search::{compute_win_chance_exact, SearchResult};
+use crate::cancel::CancelToken;
+use crate::gui::{ReportMessage, SearchMode};
+use crate::packed::{get_from_packed_state, set_in_packed_state, coins_of_state};
+use crate::parsing::read_game;
+use crate::math::count_assigned_packed;
+
+
+// what a single move of a played game was worth compared to the best move
+#[derive(Copy, Clone, Debug)]
+pub struct MoveAnalysis
+{
+    pub row: usize,
+    pub col: usize,
+    pub symbol: usize, // what the square turned out to be, 0 = bomb
+    pub best_value: f64, // value of the position when playing perfectly from here, resigning included
+    pub played_value: f64, // value of the position when flipping (row, col) and playing perfectly afterwards
+    pub cumulative_loss: f64, // sum of best_value - played_value up to and including this move
+}
+
+
+// moves are (row, col, revealed symbol) in the order they were played,
+// every prefix of the game is searched from scratch, the cache is shared though
+pub fn analyse_game(
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    moves: &[(usize, usize, usize)],
+    mode: SearchMode,
+    threads: usize,
+) -> Vec<MoveAnalysis>
+{
+    let cache = TranspositionTable::new(256, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+
+    let mut analysis = Vec::with_capacity(moves.len());
+    let mut state = 0;
+    let mut cumulative_loss = 0.0;
+
+    for &(row, col, symbol) in moves
+    {
+        if get_from_packed_state(state, row, col) != 0
+        {
+            warn!("Square ({}, {}) has been flipped twice, ignoring it", row, col);
+            continue;
+        }
+
+        let (best_value, square_values, symbol_probs) = match search_position(
+            state, &sr, &sc, &br, &bc, level, &cache, mode, threads)
+        {
+            Some(r) => r,
+            None => break,
+        };
+
+        let played_value = match square_values[row][col]
+        {
+            Some(v) => v,
+
+            // the search skips squares which can't be useful in that mode,
+            // so evaluate the outcomes of that square on their own
+            None => {
+                let mut v = 0.0;
+                for s in 1..4
+                {
+                    if symbol_probs[row][col][s] > 0.0
+                    {
+                        let child = set_in_packed_state(state, row, col, s);
+                        v += symbol_probs[row][col][s] * value_after_move(
+                            child, &sr, &sc, &br, &bc, level, &cache, mode, threads);
+                    }
+                }
+                v
+            }
+        };
+
+        cumulative_loss += best_value - played_value;
+
+        analysis.push(MoveAnalysis {
+            row,
+            col,
+            symbol,
+            best_value,
+            played_value,
+            cumulative_loss,
+        });
+
+        // that's it, bomb
+        if symbol == 0
+        {
+            break;
+        }
+
+        state = set_in_packed_state(state, row, col, symbol);
+    }
+
+    analysis
+}
+
+
+// best value, values of the squares and symbol probabilities, None if the game is over
+fn search_position(
+    state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    cache: &TranspositionTable,
+    mode: SearchMode,
+    threads: usize,
+) -> Option<(f64, [[Option<f64>;5];5], [[[f64;4];5];5])>
+{
+    let cancel = CancelToken::new();
+    let (result_reporting_sender, result_reporting_receiver) = unbounded();
+
+    let search_result = compute_win_chance_exact(
+        state, &sr, &sc, &br, &bc, level,
+        &cache,
+        &cancel,
+        &result_reporting_sender,
+        mode, threads);
+
+    let mut square_values = [[None;5];5];
+    let mut symbol_probs = [[[0.0;4];5];5];
+
+    for msg in result_reporting_receiver.try_iter()
+    {
+        match msg
+        {
+            ReportMessage::SquareWinProb(row, col, v) => square_values[row][col] = Some(v),
+            ReportMessage::SquareSymbols(sp) => symbol_probs = sp,
+            _ => (),
+        }
+    }
+
+    match search_result
+    {
+        SearchResult::SuccessfulSearchWithInfo(best_value, _, _) => Some((best_value, square_values, symbol_probs)),
+
+        SearchResult::TerminalState => {
+            info!("Game was already won before this move");
+            None
+        }
+
+        SearchResult::InconsistentPuzzle => {
+            warn!("The moves don't fit the constraints and level");
+            None
+        }
+
+        msg => panic!("Not supposed to get {:?} from the search here", msg),
+    }
+}
+
+
+// value of the state right after a move, playing perfectly afterwards
+fn value_after_move(
+    state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    cache: &TranspositionTable,
+    mode: SearchMode,
+    threads: usize,
+) -> f64
+{
+    if mode == SearchMode::SurviveNextMove
+    {
+        return 1.0; // survived it
+    }
+
+    let cancel = CancelToken::new();
+    let (result_reporting_sender, _result_reporting_receiver) = unbounded();
+
+    match compute_win_chance_exact(
+        state, &sr, &sc, &br, &bc, level,
+        &cache,
+        &cancel,
+        &result_reporting_sender,
+        mode, threads)
+    {
+        SearchResult::SuccessfulSearchWithInfo(v, _, _) => v,
+
+        // all 2 and 3 found, the round is over
+        SearchResult::TerminalState => match mode
+        {
+            SearchMode::Coins => coins_of_state(state) as f64,
+            SearchMode::WinEight => if count_assigned_packed(state) >= 8 { 1.0 } else { 0.0 },
+            _ => 1.0,
+        },
+
+        msg => panic!("Not supposed to get {:?} from the search here", msg),
+    }
+}
+
+
+// game file as read by read_game, the first line is the whole board as revealed at the end of the round
+pub fn regret_report(path: &str)
+{
+    let threads = 12;
+    let mode = SearchMode::WinChance;
+
+    let ((_, sr, sc, br, bc, level, board), flips) = read_game(path);
+
+    let moves: Vec<_> = flips
+        .into_iter()
+        .map(|(row, col)| (row, col, get_from_packed_state(board, row, col)))
+        .collect();
+
+    let analysis = analyse_game(&sr, &sc, &br, &bc, level, &moves, mode, threads);
+
+    println!("{:?} analysis of {} moves", mode, analysis.len());
+    for (i, m) in analysis.iter().enumerate()
+    {
+        if mode == SearchMode::Coins
+        {
+            println!("Move {}: ({}, {}) was a {} | best {:.2} | played {:.2} | loss {:.2} | cumulative loss {:.2}",
+                     i + 1, m.row, m.col, m.symbol, m.best_value, m.played_value,
+                     m.best_value - m.played_value, m.cumulative_loss);
+        }
+        else {
+            println!("Move {}: ({}, {}) was a {} | best {:.2}% | played {:.2}% | loss {:.2}% | cumulative loss {:.2}%",
+                     i + 1, m.row, m.col, m.symbol, m.best_value * 100.0, m.played_value * 100.0,
+                     (m.best_value - m.played_value) * 100.0, m.cumulative_loss * 100.0);
+        }
+    }
+}
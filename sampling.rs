@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+use crossbeam_channel::unbounded;
+use crate::cancel::CancelToken;
+use crate::csp_constraints::BoardSampler;
+use crate::depth_limited::{compute_depth_limited_with_boards, Heuristic};
+use crate::gui::{ReportMessage, SearchMode};
+use crate::search::{compute_win_chance_with_boards, SearchResult};
+use crate::transposition::{TranspositionTable, ReplacementPolicy, ValuePrecision};
+
+// half the width of a 95% confidence interval in standard errors
+const Z_95: f64 = 1.96;
+
+
+// what's searched on each sample of boards
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SampledSearch
+{
+    Exact,
+    DepthLimited(usize, Heuristic), // max. flips, leaf heuristic
+}
+
+// value of a square over all runs, the true value of the sampled search is within mean +- half_width
+// with 95% confidence
+#[derive(Copy, Clone, Debug)]
+pub struct SquareEstimate
+{
+    pub mean: f64,
+    pub half_width: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct SampledResult
+{
+    pub square: (usize, usize), // best mean
+    pub estimates: [[Option<SquareEstimate>;5];5], // None if a run didn't search that square
+    pub boards: usize, // boards that fit the sums and Voltorbs of the position, see BoardSampler::boards
+    pub seconds: f64,
+}
+
+
+// Determinized search: every run draws that many samples of the possible boards (with replacement,
+// by their level weight) and searches as if those were all the boards, the runs are averaged per square.
+// Optimizing for the sample overrates every square a bit, so compare squares with each other,
+// not with the values of the exact search.
+// The boards are drawn straight from the row patterns (see BoardSampler), the possible boards are never listed.
+// A sample isn't closed under the symmetries of the puzzle, so the runs search without them.
+// The exact runs share a private table of cache_mb MB, cleared for every run, values of a sample must not
+// end up next to the ones of the real puzzle.
+// Err(InconsistentPuzzle), Err(TerminalState) or Err(Aborted) if there's nothing to report, TerminalState
+// if every run only drew boards that are won already. Runs like that don't count for the squares
+pub fn compute_win_chance_sampled(
+    org_packed_state: u64,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    cache_mb: usize,
+    cancel: &CancelToken,
+    mode: SearchMode,
+    threads: usize,
+    search: SampledSearch,
+    samples: usize,
+    runs: usize,
+    seed: u64,
+) -> Result<SampledResult, SearchResult>
+{
+    let start_of_computation = Instant::now();
+
+    let sampler = BoardSampler::new(org_packed_state, &sr, &sc, &br, &bc, level);
+
+    let mut rng = XorShift::new(seed);
+
+    // only the exact search needs one
+    let cache = match search
+    {
+        SampledSearch::Exact => Some(TranspositionTable::new(cache_mb, ReplacementPolicy::TwoTier, ValuePrecision::F64)),
+        SampledSearch::DepthLimited(_, _) => None,
+    };
+
+    // per square: sum of values, sum of squared values, runs that searched it
+    let mut sums = [[(0.0, 0.0, 0);5];5];
+    let mut searched_runs = 0;
+
+    for run in 0..runs
+    {
+        let (s_pb, s_count, s_weights) = match draw_boards(&sampler, samples, &mut rng)
+        {
+            Some(drawn) => drawn,
+            None => return Err(SearchResult::InconsistentPuzzle),
+        };
+
+        let (result_reporting_sender, result_reporting_receiver) = unbounded();
+
+        let search_result = match search
+        {
+            SampledSearch::Exact => {
+                let cache = cache.as_ref().expect("The exact search has a table");
+
+                // values of another sample are wrong for this one
+                cache.clear();
+
+                compute_win_chance_with_boards(
+                    org_packed_state,
+                    &s_pb, &s_count, &s_weights, true,
+                    &sr, &sc, &br, &bc, level,
+                    cache, cancel, &result_reporting_sender,
+                    mode, threads)
+            }
+
+            SampledSearch::DepthLimited(max_flips, heuristic) => {
+                compute_depth_limited_with_boards(
+                    org_packed_state,
+                    &s_pb, &s_count, &s_weights, true,
+                    &sr, &sc, &br, &bc, level,
+                    cancel, &result_reporting_sender,
+                    mode, threads, max_flips, heuristic)
+            }
+        };
+
+        match search_result
+        {
+            SearchResult::SuccessfulSearchWithInfo(value, time, _) => {
+                info!("Thread: Run {} on {} boards worth {} after {} seconds", run, s_pb.len(), value, time);
+                searched_runs += 1;
+            }
+
+            // no 2 or 3 left in the boards drawn, the squares aren't searched in this run
+            SearchResult::TerminalState => {
+                info!("Thread: Run {} only drew boards that are won already", run);
+            }
+
+            result => return Err(result),
+        }
+
+        for msg in result_reporting_receiver.try_iter()
+        {
+            if let ReportMessage::SquareWinProb(row, col, value) = msg
+            {
+                let (sum, sum_of_squares, n) = &mut sums[row][col];
+                *sum += value;
+                *sum_of_squares += value * value;
+                *n += 1;
+            }
+        }
+    }
+
+    let mut estimates = [[None;5];5];
+    let mut square = None;
+    let mut best_mean = f64::MIN;
+
+    for row in 0..5
+    {
+        for col in 0..5
+        {
+            let (sum, sum_of_squares, n) = sums[row][col];
+
+            // a square that isn't in every searched sample can't be compared fairly
+            if n < searched_runs || n == 0
+            {
+                continue;
+            }
+
+            let mean = sum / n as f64;
+            let variance = if n > 1 { (sum_of_squares - n as f64 * mean * mean).max(0.0) / (n - 1) as f64 } else { 0.0 };
+
+            estimates[row][col] = Some(SquareEstimate {
+                mean,
+                half_width: Z_95 * (variance / n as f64).sqrt(),
+            });
+
+            if mean > best_mean
+            {
+                best_mean = mean;
+                square = Some((row, col));
+            }
+        }
+    }
+
+    if searched_runs == 0
+    {
+        return Err(SearchResult::TerminalState);
+    }
+
+    match square
+    {
+        Some(square) => Ok(SampledResult {
+            square,
+            estimates,
+            boards: sampler.boards(),
+            seconds: start_of_computation.elapsed().as_secs_f64(),
+        }),
+
+        None => Err(SearchResult::Aborted),
+    }
+}
+
+
+// samples boards drawn by weight, as boards, counts and weights like find_possible_boards returns them.
+// A board drawn k times is a board of weight k. None if no board fits the puzzle
+fn draw_boards(
+    sampler: &BoardSampler,
+    samples: usize,
+    rng: &mut XorShift,
+) -> Option<(Vec<u64>, Vec<usize>, Vec<f64>)>
+{
+    let mut drawn: HashMap<u64, usize> = HashMap::new();
+    for _ in 0..samples
+    {
+        let board = sampler.draw(&mut || rng.next_f64())?;
+        *drawn.entry(board).or_insert(0) += 1;
+    }
+
+    // sorted, so the same draws always give the same boards
+    let mut by_times: BTreeMap<usize, Vec<u64>> = BTreeMap::new();
+    for (board, times) in drawn
+    {
+        by_times.entry(times).or_default().push(board);
+    }
+
+    let mut s_pb = Vec::with_capacity(samples);
+    let mut s_count = Vec::with_capacity(by_times.len());
+    let mut s_weights = Vec::with_capacity(by_times.len());
+    for (times, mut boards) in by_times
+    {
+        boards.sort_unstable();
+        s_count.push(boards.len());
+        s_weights.push(times as f64);
+        s_pb.extend(boards);
+    }
+
+    Some((s_pb, s_count, s_weights))
+}
+
+
+// xorshift64*, plenty for drawing boards and the runs are reproducible from the seed
+struct XorShift
+{
+    state: u64,
+}
+
+impl XorShift
+{
+    fn new(seed: u64) -> XorShift
+    {
+        // zero would stay zero forever
+        XorShift { state: seed.max(1) }
+    }
+
+    // uniform in [0, 1)
+    fn next_f64(&mut self) -> f64
+    {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
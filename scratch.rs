@@ -0,0 +1,66 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::board_index::BoardIndex;
+
+
+// what a running search task writes to: squares to try and boards left, both by depth
+pub struct Scratch
+{
+    pub squares_by_depth: Vec<Vec<(usize,usize)>>,
+    pub board_sets: Vec<Vec<u64>>,
+}
+
+// Scratch buffers are handed to a task and come back when it's done,
+// so there are about as many as threads working at the same time and not one per job.
+// Everything else a task reads (BoardIndex) is shared
+pub struct ScratchPool<'a>
+{
+    board_index: &'a BoardIndex,
+    free: Mutex<Vec<Scratch>>,
+    created: AtomicUsize,
+}
+
+impl<'a> ScratchPool<'a>
+{
+    pub fn new(board_index: &'a BoardIndex) -> ScratchPool<'a>
+    {
+        ScratchPool {
+            board_index,
+            free: Mutex::new(Vec::new()),
+            created: AtomicUsize::new(0),
+        }
+    }
+
+    // board_sets[0] holds all boards of the root, deeper levels are whatever the last task left
+    pub fn take(&self) -> Scratch
+    {
+        if let Some(scratch) = self.free.lock().expect("Failed to lock scratch pool").pop()
+        {
+            return scratch;
+        }
+
+        self.created.fetch_add(1, Ordering::Relaxed);
+
+        let mut squares_by_depth = Vec::with_capacity(26);
+        for _ in 0..26
+        {
+            squares_by_depth.push(Vec::with_capacity(25));
+        }
+
+        Scratch {
+            squares_by_depth,
+            board_sets: self.board_index.sets_by_depth(),
+        }
+    }
+
+    pub fn give_back(&self, scratch: Scratch)
+    {
+        self.free.lock().expect("Failed to lock scratch pool").push(scratch);
+    }
+
+    // number of scratch buffers that had to be allocated
+    pub fn created(&self) -> usize
+    {
+        self.created.load(Ordering::Relaxed)
+    }
+}
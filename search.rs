@@ -1,5 +1,7 @@
 use crate::{csp_constraints, packed};
-use crate::possible_boards::{accumulate_symbol_weights, filter_possible_boards_of_next_depth};
+use crate::board_index::BoardIndex;
+use crate::scratch::ScratchPool;
+use crate::cancel::CancelToken;
 use float_ord::FloatOrd;
 use crate::math::{transpose_in_place, count_assigned, print_board, count_special, print_board_with_cons, transpose_packed, count_assigned_packed};
 use std::collections::{HashSet, HashMap};
@@ -8,17 +10,31 @@ use std::fs::File;
 use std::io::{BufWriter, Write};
 use crate::csp_constraints::find_possible_boards;
 use std::convert::TryInto;
-use crate::gui::{ControlMessage, ReportMessage, SearchMode};
-use crossbeam_channel::{Sender, Receiver, unbounded, tick, RecvError};
+use crate::gui::{ReportMessage, SearchMode};
+use crossbeam_channel::{Sender, unbounded, tick, RecvError};
 use crate::search::SearchResult::SuccessfulSearch;
-use dashmap::DashMap;
+use crate::transposition::{TranspositionTable, fingerprint};
+use crate::symmetry::Symmetries;
+use crate::stats::{SearchStats, USE_DEPTH_STATISTICS};
 use rayon::prelude::*;
 use std::time::Instant;
 use std::collections::hash_map::RandomState;
 use dashmap::mapref::multiple::RefMulti;
 use std::thread;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tinyvec::array_vec;
 
+// in WinChance mode the value only depends on the boards that are left,
+// so states with the same boards share one cache entry (costs filtering the boards before the lookup)
+const USE_BOARD_SET_KEYS: bool = true;
+// skip a square whose symbol always follows from the symbol of a square searched before it that's
+// at least as safe, costs comparing their bitsets whenever the cheap bound doesn't cut already.
+// Only in the modes where that pays, see is_dominated
+const USE_DOMINANCE: bool = true;
+// nodes above this depth search their squares in parallel, deeper ones are too small to be worth it.
+// Only measured on one core, where 2 to 4 are the same within noise (see benchmark_threads)
+const PARALLEL_DEPTH: usize = 3;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum SearchResult
 {
@@ -36,47 +52,57 @@ pub fn compute_win_chance_exact(
     br: &[usize; 5],
     bc: &[usize; 5],
     level: usize,
-    cache_chances: &DashMap<u64, f64>,
-    from_gui: &Receiver<ControlMessage>,
+    cache_chances: &TranspositionTable,
+    cancel: &CancelToken,
     to_gui: &Sender<ReportMessage>,
-    to_thread: &Sender<ControlMessage>,
     mode: SearchMode,
     threads: usize,
 ) -> SearchResult
 {
-    let start_of_computation = Instant::now();
-
     // get possible boards for original state
     let (o_pb, o_count, weights) = find_possible_boards(org_packed_state, &sr, &sc, &br, &bc, level);
-    log::info!("Thread: Found {} states", o_pb.len());
 
-    // one big array for all depths and weights,
-    // an list of indices marks the start of a depth-weight group,
-    // including a pointer at the end
-    let mut possible_boards = Vec::with_capacity(1024 * 1024);
-    let mut indices = vec![0; weights.len() * 26 + 1];
+    compute_win_chance_with_boards(
+        org_packed_state,
+        &o_pb, &o_count, &weights, false,
+        &sr, &sc, &br, &bc, level,
+        cache_chances, cancel, to_gui,
+        mode,
+        threads)
+}
 
-    // put in all the boards
-    for i in 0..o_pb.len()
-    {
-        possible_boards.push(o_pb[i]);
-    }
+// same as compute_win_chance_exact with the possible boards of the original state
+// (as returned by find_possible_boards) already known, or only a sample of them if is_sample
+pub fn compute_win_chance_with_boards(
+    org_packed_state: u64,
+    o_pb: &Vec<u64>,
+    o_count: &Vec<usize>,
+    weights: &Vec<f64>,
+    is_sample: bool,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    cache_chances: &TranspositionTable,
+    cancel: &CancelToken,
+    to_gui: &Sender<ReportMessage>,
+    mode: SearchMode,
+    threads: usize,
+) -> SearchResult
+{
+    let start_of_computation = Instant::now();
 
-    // set the indices
-    let mut index = 0;
+    // values of other puzzles or modes must never be reused
+    cache_chances.bind(fingerprint(&sr, &sc, &br, &bc, level, mode));
 
-    // depth 0 starts at index 0
-    indices[0] = 0;
-    // add the start indices of each weight group after that
-    // including the first empty index
-    for j in 0..o_count.len()
-    {
-        index += o_count[j];
-        indices[j + 1] = index;
-    }
+    log::info!("Thread: Found {} states", o_pb.len());
+
+    // read-only for all threads, they only keep their own scratch buffers
+    let board_index = BoardIndex::new(&o_pb, &o_count, &weights);
+    let scratch = ScratchPool::new(&board_index);
 
-    let acc = accumulate_symbol_weights(0, &possible_boards, &indices,
-                                        0, &weights);
+    let acc = board_index.accumulate(&board_index.root_set());
 
     if o_pb.is_empty()
     {
@@ -87,46 +113,29 @@ pub fn compute_win_chance_exact(
             .expect("Failed to send symbol prob array to GUI");
     }
 
-    let mut squares_by_depth = Vec::with_capacity(26);
-    for d in 0..26
-    {
-        squares_by_depth.push(Vec::with_capacity(25));
-    }
+    // equivalent states share one cache entry, a sample isn't closed under the symmetries
+    let symmetries = if is_sample { Symmetries::none() } else { Symmetries::new(&sr, &sc, &br, &bc) };
+    let stats = SearchStats::new();
 
     let search_result = sh_exact_root(
         org_packed_state,
-        &mut squares_by_depth,
-        &mut possible_boards,
-        &mut indices,
-        &weights,
+        &board_index,
+        &scratch,
         &sr, &sc, &br, &bc, level,
         cache_chances,
-        &from_gui,
+        &symmetries,
+        &stats,
+        &cancel,
         &to_gui,
-        &to_thread,
         mode,
         threads,
     );
 
-    // TODO abort faster when changing threads or cache user input/adapt input handling so GUI appears fluent
-    if search_result == SearchResult::Aborted
+    // also of an aborted search, the hard puzzles are the ones that get stopped
+    if USE_DEPTH_STATISTICS && matches!(search_result, SearchResult::SuccessfulSearch(_) | SearchResult::Aborted)
     {
-        // Eat that one stop message
-        info!("Thread: Filtering out the stop message since search has been aborted");
-        loop
-        {
-            match from_gui.recv().expect("from_gui said it's not empty but recv() failed")
-            {
-                ControlMessage::Stop => {
-                    info!("Thread: Found and killed stop message, let's continue");
-                    break;
-                },
-
-                msg => {
-                    panic!("Not supposed to receive {:?} here", msg);
-                },
-            }
-        }
+        to_gui.send(ReportMessage::Statistics(stats.per_depth()))
+            .expect("Failed to send search statistics to GUI");
     }
 
     return if let SearchResult::SuccessfulSearch(p) = search_result
@@ -135,6 +144,8 @@ pub fn compute_win_chance_exact(
         let nodes = cache_chances.len();
 
         info!("Thread: Successfully computed {} nodes in {} seconds", nodes, dur.as_secs_f64());
+        info!("Thread: Needed {} scratch buffers", scratch.created());
+        stats.report();
 
         SearchResult::SuccessfulSearchWithInfo(p, dur.as_secs_f64(), nodes)
     }
@@ -146,41 +157,36 @@ pub fn compute_win_chance_exact(
 // separate root function to keep things clean and parallelize at root level
 fn sh_exact_root(
     state: u64,
-    squares_by_depth: &mut Vec<Vec<(usize,usize)>>,
-    possible_boards: &mut Vec<u64>,
-    indices: &mut Vec<usize>,
-    weights: &Vec<f64>,
+    board_index: &BoardIndex,
+    scratch: &ScratchPool,
     sr: &[usize; 5],
     sc: &[usize; 5],
     br: &[usize; 5],
     bc: &[usize; 5],
     level: usize,
-    cache_chances: &DashMap<u64, f64>,
-    from_gui: &Receiver<ControlMessage>,
+    cache_chances: &TranspositionTable,
+    symmetries: &Symmetries,
+    stats: &SearchStats,
+    cancel: &CancelToken,
     to_gui: &Sender<ReportMessage>,
-    to_thread: &Sender<ControlMessage>,
     mode: SearchMode,
     threads: usize,
 ) -> SearchResult
 {
-    let depth = 0;
-    let index_start = 0;
-    let index_end = weights.len();
+    let root_set = board_index.root_set();
 
-    if is_won_state(state, possible_boards, &indices, index_start, index_end)
+    if board_index.is_won(state, &root_set)
     {
         info!("Thread: Root state is terminal state, all 2/3 found");
         return SearchResult::TerminalState;
     }
 
-    let acc = accumulate_symbol_weights(
-        state, possible_boards, &indices, index_start, weights
-    );
+    let acc = board_index.accumulate(&root_set);
 
     match mode
     {
         SearchMode::WinChance => {
-            if is_won_state(state, possible_boards, &indices, index_start, index_end)
+            if board_index.is_won(state, &root_set)
             {
                 return SearchResult::SuccessfulSearch(1.0);
             }
@@ -195,7 +201,7 @@ fn sh_exact_root(
 
         SearchMode::WinEight => {
             if count_assigned_packed(state) >= 8 &&
-                is_won_state(state, possible_boards, &indices, index_start, index_end)
+                board_index.is_won(state, &root_set)
             {
                 return SearchResult::SuccessfulSearch(1.0);
             }
@@ -213,7 +219,7 @@ fn sh_exact_root(
         }
 
         SearchMode::Coins => {
-            if is_won_state(state, possible_boards, &indices, index_start, index_end)
+            if board_index.is_won(state, &root_set)
             {
                 return SearchResult::SuccessfulSearch(coins_of_state(state) as f64);
             }
@@ -221,6 +227,7 @@ fn sh_exact_root(
     }
 
     let mut jobs_per_square = [[0;5];5];
+    let mut squares = Vec::with_capacity(25); // the ones that get searched
 
     let (send_results, rec_results) = unbounded();
     let mut jobs = Vec::with_capacity(3 * 25);
@@ -240,11 +247,16 @@ fn sh_exact_root(
                         if acc[row][col][symbol] != 0.0
                         {
                             let state = set_in_packed_state(state, row, col, symbol);
-                            let job = (row, col, symbol, state, squares_by_depth.clone(), possible_boards.clone(), indices.clone());
+                            let job = (row, col, symbol, state);
                             jobs.push(job);
                             jobs_per_square[row][col] += 1;
                         }
                     }
+
+                    if jobs_per_square[row][col] > 0
+                    {
+                        squares.push((row, col));
+                    }
                 }
             }
         }
@@ -252,100 +264,122 @@ fn sh_exact_root(
 
     let nr_jobs = jobs.len();
 
-    #[derive(Copy, Clone, Debug)]
-    pub enum Ticket
-    {
-        Ticket // Permission to be an active thread
-    }
+    // a symbol that hasn't been searched yet can't be worth more than this (same as upper_bound_ev in sh_square)
+    let optimistic = optimistic_value(&board_index, &root_set, mode);
 
-    let (ticket_sender, ticket_receiver) = unbounded();
-    for _ in 0..threads
+    let mut upper_bounds = [[0.0;5];5];
+    for &(row, col, symbol, _) in &jobs
     {
-        ticket_sender.send(Ticket::Ticket).expect("Failed to send ticket");
+        upper_bounds[row][col] += acc[row][col][symbol] * optimistic;
     }
 
-    jobs.into_par_iter().for_each(
-        {
-            |(row, col, symbol, state, mut squares_by_depth, mut possible_boards, mut indices)| {
-
-                match ticket_receiver.recv()
-                {
-                    Ok(msg) => {
-                        match msg
-                        {
-                            Ticket::Ticket => {
-
-                                // do the work
-                                let search_result = sh_exact(
-                                    1,
-                                    state,
-                                    &mut squares_by_depth,
-                                    &mut possible_boards,
-                                    &mut indices,
-                                    index_start + &weights.len(),
-                                    &weights,
-                                    &sr, &sc, &br, &bc, level,
-                                    &cache_chances,
-                                    &from_gui,
-                                    &to_thread,
-                                    mode,
-                                );
-
-                                &send_results.send((row, col, symbol, search_result))
-                                    .expect("Failed to send search result to root search thread");
-
-                                // put back the ticket
-                                ticket_sender.send(Ticket::Ticket).expect("Failed to put back ticket");
-                            }
-                        }
-                    }
-                    Err(_) => panic!("Error while waiting for ticket"),
-                }
-            }
-        }
-    );
-
     let mut received = 0;
     let mut aborted = false;
 
-    let mut best_val_so_far = 0.0;
-    let mut win_chances = [[0.0;5];5]; // just for determining the max.
+    // stopping here is an option too, it keeps the coins in Coins mode
+    let resign = resign_value(state, mode);
+    let mut best_val_so_far = resign;
+    let mut win_chances = [[0.0;5];5]; // just for determining the max. and the lower bounds
+    let mut outcome_values = [[[None;4];5];5]; // what each symbol of a square leads to
+
+    report_root_bounds(&squares, &win_chances, &upper_bounds, resign, to_gui);
+
+    // exactly that many active threads, a subtree that takes longer than the others
+    // gets split up further down (see PARALLEL_DEPTH) and idle threads steal the pieces
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("Failed to build search thread pool");
+
+    // the jobs run on the pool while this thread collects their results,
+    // so the bounds reach the GUI while the search is still going
+    pool.in_place_scope(|scope| {
+        scope.spawn(move |_| jobs.into_par_iter().for_each(
+            {
+                |(row, col, symbol, state)| {
+
+                    let mut buffers = scratch.take();
+
+                    // do the work
+                    let search_result = sh_exact(
+                        1,
+                        state,
+                        (row, col, symbol),
+                        None,
+                        &mut buffers.squares_by_depth,
+                        &board_index,
+                        &mut buffers.board_sets,
+                        &sr, &sc, &br, &bc, level,
+                        &cache_chances,
+                        &symmetries,
+                        &scratch,
+                        &stats,
+                        &cancel,
+                        mode,
+                    );
+
+                    scratch.give_back(buffers);
+
+                    send_results.send((row, col, symbol, search_result))
+                        .expect("Failed to send search result to root search thread");
+                }
+            }
+        ));
 
-    while received < nr_jobs
-    {
-        match rec_results.recv()
+        while received < nr_jobs
         {
-            Ok((row, col, symbol, search_result)) =>
-                {
-                    if let SearchResult::SuccessfulSearch(p) = search_result
+            match rec_results.recv()
+            {
+                Ok((row, col, symbol, search_result)) =>
                     {
-                        win_chances[row][col] += p * acc[row][col][symbol];
-                        jobs_per_square[row][col] -= 1;
-
-                        if jobs_per_square[row][col] == 0
+                        if let SearchResult::SuccessfulSearch(p) = search_result
                         {
-                            // that square is done, notify GUI about it
-                            to_gui.send(ReportMessage::SquareWinProb(row, col, win_chances[row][col]))
-                                .expect("Failed to send win probability for square");
+                            win_chances[row][col] += p * acc[row][col][symbol];
+                            upper_bounds[row][col] -= (optimistic - p) * acc[row][col][symbol];
+                            outcome_values[row][col][symbol] = Some(p);
+                            jobs_per_square[row][col] -= 1;
 
-                            if win_chances[row][col] > best_val_so_far
+                            if jobs_per_square[row][col] == 0
                             {
-                                best_val_so_far = win_chances[row][col];
+                                // no rounding errors left in the bounds of a finished square
+                                upper_bounds[row][col] = win_chances[row][col];
+
+                                // that square is done, notify GUI about it
+                                // a bomb always ends the game with nothing
+                                if acc[row][col][0] > 0.0
+                                {
+                                    outcome_values[row][col][0] = Some(0.0);
+                                }
+                                to_gui.send(ReportMessage::SquareOutcomes(row, col, outcome_values[row][col]))
+                                    .expect("Failed to send outcome values for square");
+                                to_gui.send(ReportMessage::SquareWinProb(row, col, win_chances[row][col]))
+                                    .expect("Failed to send win probability for square");
+
+                                if win_chances[row][col] > best_val_so_far
+                                {
+                                    best_val_so_far = win_chances[row][col];
+                                }
+                            }
+                            else {
+                                to_gui.send(ReportMessage::SquareBounds(row, col, win_chances[row][col], upper_bounds[row][col]))
+                                    .expect("Failed to send bounds for square");
                             }
+
+                            report_root_bounds(&squares, &win_chances, &upper_bounds, resign, to_gui);
                         }
-                    }
-                    else if let SearchResult::Aborted = search_result {
-                        aborted = true;
+                        else if let SearchResult::Aborted = search_result {
+                            aborted = true;
+                        }
+
+                        received += 1;
                     }
 
-                    received += 1;
+                Err(e) => {
+                    panic!("Root search thread failed to receive results from search thread: {}", e);
                 }
-
-            Err(e) => {
-                panic!("Root search thread failed to receive results from search thread: {}", e);
             }
         }
-    }
+    });
 
     return if aborted {
         SearchResult::Aborted
@@ -355,179 +389,135 @@ fn sh_exact_root(
 }
 
 
+// the root is worth at least as much as the best lower bound of its squares or resigning
+// and at most as much as the best upper bound or resigning, the square with the best lower bound is the best move so far
+fn report_root_bounds(
+    squares: &Vec<(usize, usize)>,
+    lower_bounds: &[[f64;5];5],
+    upper_bounds: &[[f64;5];5],
+    resign: f64,
+    to_gui: &Sender<ReportMessage>,
+)
+{
+    if let Some(&(best_row, best_col)) = squares.iter().max_by_key(
+        |&&(row, col)| (FloatOrd(lower_bounds[row][col]), FloatOrd(upper_bounds[row][col])))
+    {
+        let upper = squares.iter()
+            .map(|&(row, col)| upper_bounds[row][col])
+            .fold(resign, f64::max);
+        let lower = lower_bounds[best_row][best_col].max(resign);
+
+        to_gui.send(ReportMessage::RootBounds(lower, upper, best_row, best_col))
+            .expect("Failed to send bounds of the root");
+    }
+}
+
 fn sh_exact(
     depth: usize,
-    mut state: u64,
+    state: u64,
+    revealed: (usize, usize, usize), // (row, col, symbol) the parent revealed to get here
+    known_optimistic: Option<f64>, // optimistic value of the boards left if the parent has it already
     squares_by_depth: &mut Vec<Vec<(usize,usize)>>,
-    possible_boards: &mut Vec<u64>,
-    indices: &mut Vec<usize>,
-    index_start: usize,
-    weights: &Vec<f64>,
+    board_index: &BoardIndex,
+    board_sets: &mut Vec<Vec<u64>>,
     sr: &[usize; 5],
     sc: &[usize; 5],
     br: &[usize; 5],
     bc: &[usize; 5],
     level: usize,
-    cache: &DashMap<u64, f64>,
-    from_gui: &Receiver<ControlMessage>,
-    to_thread: &Sender<ControlMessage>,
+    cache: &TranspositionTable,
+    symmetries: &Symmetries,
+    scratch: &ScratchPool,
+    stats: &SearchStats,
+    cancel: &CancelToken,
     mode: SearchMode,
 ) -> SearchResult
 {
-    if let Some(r) = cache.get(&state)
+    let mut key = symmetries.canonical(state);
+
+    if let Some(r) = cache.get(&key)
     {
-        return SearchResult::SuccessfulSearch(*r);
+        stats.count_lookup(depth, true, false);
+        return SearchResult::SuccessfulSearch(r);
     }
 
-    if depth == 6
+    // every thread notices within one node
+    if cancel.is_cancelled()
     {
-        match from_gui.try_recv()
-        {
-            Err(_) => (),
-            Ok(msg) => match msg
-            {
-                ControlMessage::Stop => {
-                    to_thread.send(ControlMessage::Stop)
-                        .expect("Failed to pass Stop message on to other search threads");
-                    return SearchResult::Aborted;
-                },
-
-                msg => {
-                    panic!("Not supposed to receive {:?} here, only stop", msg);
-                },
-            }
-        }
+        return SearchResult::Aborted;
     }
 
-    // only create possible boards (expensive operation) after the cheaper checks
-    filter_possible_boards_of_next_depth(
-        state,
-        possible_boards,
-        indices,
-        index_start - weights.len(),
-        weights,
-    );
-
-    // when only one square is assigned?
-    let max_coins = possible_boards
-        .iter()
-        .skip(indices[index_start])
-        .map(|board| coins_of_state(*board))
-        .max()
-        .unwrap() as f64;
-
-    let index_end = index_start + weights.len();
+    // only narrow down the boards (one AND per word) after the cheaper checks
+    let (row, col, symbol) = revealed;
+    let start_of_filtering = stats.start_timer();
+    let (parents, children) = board_sets.split_at_mut(depth);
+    board_index.restrict(&parents[depth - 1], row, col, symbol, &mut children[0]);
+    let boards = &board_sets[depth];
+    stats.add_filter_time(depth, start_of_filtering);
 
-    // TODO Lost states are modeled implicitly by not adding anything
-    // But if I bomb after having uncovered x squares, haven't I still won?
-    // Is that handled?
-    // TODO in WinEight mode, the program recommended a 100% three ending with less than 8 squares
-    match mode
+    if USE_BOARD_SET_KEYS && mode == SearchMode::WinChance
     {
-        SearchMode::WinChance => {
-            if is_won_state(state, possible_boards, &indices, index_start, index_end)
-            {
-                return SearchResult::SuccessfulSearch(1.0);
-            }
-        }
+        let board_set_key = symmetries.canonical(board_index.board_set_state(boards));
 
-        SearchMode::SurviveLevel => {
-            if count_assigned_packed(state) >= level
-            {
-                return SearchResult::SuccessfulSearch(1.0);
-            }
-        }
+        if board_set_key != key
+        {
+            key = board_set_key;
 
-        SearchMode::WinEight => {
-            if is_won_state(state, possible_boards, &indices, index_start, index_end)
+            if let Some(r) = cache.get(&key)
             {
-                return if count_assigned_packed(state) >= 8
-                {
-                    SearchResult::SuccessfulSearch(1.0)
-                } else {
-                    // winning too early isn't worth anything
-                    SearchResult::SuccessfulSearch(0.0)
-                }
+                stats.count_lookup(depth, true, true);
+                return SearchResult::SuccessfulSearch(r);
             }
-        }
 
-        SearchMode::SurviveEight => {
-            if count_assigned_packed(state) >= 8
-            {
-                return SearchResult::SuccessfulSearch(1.0);
-            }
+            stats.count_lookup(depth, false, true);
         }
-
-        SearchMode::SurviveNextMove => {
-            return SearchResult::SuccessfulSearch(1.0); // We've survived the next move :O
+        else {
+            stats.count_lookup(depth, false, false);
         }
+    }
+    else {
+        stats.count_lookup(depth, false, false);
+    }
 
-        SearchMode::Coins => {
-            if is_won_state(state, possible_boards, &indices, index_start, index_end)
-            {
-                return SearchResult::SuccessfulSearch(coins_of_state(state) as f64);
-            }
-        }
+    if let Some(v) = terminal_value(state, || board_index.is_won(state, boards), level, mode)
+    {
+        return SearchResult::SuccessfulSearch(v);
     }
 
-    let pb_left = indices[index_end] - indices[index_start];
+    let pb_left = board_index.count(boards);
 
-    if pb_left >= 10
+    if pb_left >= 10 && symmetries.has_equal_lines()
     {
         match try_swap_one_row_or_col(state,
                                       &sr, &sc, &br, &bc,
-                                      cache)
+                                      cache, symmetries)
         {
             Some(r) => {
+                stats.count_swap_hit(depth);
                 return SearchResult::SuccessfulSearch(r);
             },
             None => (),
         }
     }
 
-    let acc = accumulate_symbol_weights(
-        state, possible_boards, &indices, index_start, weights
-    );
+    stats.count_node(depth, pb_left);
 
-    squares_by_depth[depth].clear();
-    for row in 0..5
-    {
-        for col in 0..5
-        {
-            if get_from_packed_state(state, row, col) == 0
-            {
-                // 'useless' squares might be worthy of being picked in certain modes
-                if acc[row][col][2] > 0.0 || acc[row][col][3] > 0.0 ||
-                    mode == SearchMode::WinEight ||
-                    mode == SearchMode::SurviveEight ||
-                    mode == SearchMode::SurviveLevel
-                {
-                    if is_good_assignment(state, &sr, &sc, &br, &bc, row, col)
-                    {
-                        squares_by_depth[depth].push((row, col));
-                    }
-                }
-            }
-        }
-    }
+    let start_of_accumulation = stats.start_timer();
+    let acc = board_index.accumulate(boards);
+    stats.add_accumulate_time(depth, start_of_accumulation);
 
-    squares_by_depth[depth].sort_unstable_by_key(
-        |&(row, col)| {
-            FloatOrd(1024.0 * acc[row][col][0] - acc[row][col][2] - acc[row][col][3])
-        }
-    );
+    let optimistic = known_optimistic.unwrap_or_else(|| optimistic_value(board_index, boards, mode));
 
-    let mut best_value_so_far = {
-        if mode == SearchMode::Coins
-        {
-            coins_of_state(state) as f64 // resigning is an option too
-        }
-        else {
-            0.0
-        }
-    };
+    candidate_squares(state, &acc, &sr, &sc, &br, &bc, symmetries, mode, &mut squares_by_depth[depth]);
+
+    let mut best_value_so_far = resign_value(state, mode);
 
-    for i in 0..squares_by_depth[depth].len()
+    // near the root the first square is searched alone to get a good bound,
+    // then its brothers in parallel (young brothers wait)
+    let parallel = depth < PARALLEL_DEPTH && squares_by_depth[depth].len() > 1;
+    let sequential = if parallel { 1 } else { squares_by_depth[depth].len() };
+
+    for i in 0..sequential
     {
         let (row, col) = squares_by_depth[depth][i];
 
@@ -550,84 +540,316 @@ fn sh_exact(
             }
         }
 
-        let mut expected_value = 0.0;
-        let prob_not_bomb = acc[row][col][1] + acc[row][col][2] + acc[row][col][3];
-        let mut upper_bound_ev = {
-            if mode != SearchMode::Coins
-            {
-                prob_not_bomb
-            }
-            else {
-                prob_not_bomb * max_coins
-            }
+        if USE_DOMINANCE && is_dominated(&squares_by_depth[depth], i, &acc, optimistic, best_value_so_far,
+                                         board_index, &board_sets[depth], mode)
+        {
+            stats.count_dominated();
+            continue;
+        }
+
+        let expected_value = match sh_square(
+            depth, state, row, col, &acc, optimistic, best_value_so_far,
+            squares_by_depth, board_index, board_sets,
+            &sr, &sc, &br, &bc, level,
+            cache, symmetries, scratch, stats, &cancel, mode,
+        ) {
+            Some(v) => v,
+            None => return SearchResult::Aborted,
         };
 
-        for symbol in 1..4
+        if expected_value > best_value_so_far
         {
-            if acc[row][col][symbol] == 0.0
-            {
-                continue;
-            }
+            best_value_so_far = expected_value;
+        }
+
+        if is_best_square(&acc, row, col, mode)
+        {
+            cache.insert(key, best_value_so_far);
+            return SuccessfulSearch(best_value_so_far);
+        }
+    }
 
-            // cut if it can't be better than current best
-            if expected_value + upper_bound_ev <= best_value_so_far
+    if parallel
+    {
+        // values are never negative, so their bits are ordered like the values themselves
+        let shared_best = AtomicU64::new(f64::to_bits(best_value_so_far));
+
+        let brothers = squares_by_depth[depth][1..].to_vec();
+        let aborted = brothers.par_iter().enumerate().any(|(i, &(row, col))| {
+            let bound = f64::from_bits(shared_best.load(Ordering::Relaxed));
+
+            // the square it follows from is searched (or being searched) by another job
+            if USE_DOMINANCE && is_dominated(&squares_by_depth[depth], i + 1, &acc, optimistic, bound,
+                                             board_index, &board_sets[depth], mode)
             {
-                break;
+                stats.count_dominated();
+                return false;
             }
 
-            state = set_in_packed_state(state, row, col, symbol);
+            // only the boards of this node are needed, deeper levels get overwritten
+            let mut buffers = scratch.take();
+            buffers.board_sets[depth].copy_from_slice(&board_sets[depth]);
 
-            if let SearchResult::SuccessfulSearch(r) = sh_exact(
-                depth + 1,
-                state,
-                squares_by_depth,
-                possible_boards,
-                indices,
-                index_start + weights.len(),
-                weights,
+            let result = sh_square(
+                depth, state, row, col, &acc, optimistic, bound,
+                &mut buffers.squares_by_depth, board_index, &mut buffers.board_sets,
                 &sr, &sc, &br, &bc, level,
-                cache,
-                &from_gui,
-                &to_thread,
-                mode,
-            ) {
-                expected_value += r * acc[row][col][symbol];
-                upper_bound_ev -= acc[row][col][symbol];
-            } else {
-                return SearchResult::Aborted;
+                cache, symmetries, scratch, stats, &cancel, mode,
+            );
+
+            scratch.give_back(buffers);
+
+            match result
+            {
+                Some(v) => {
+                    shared_best.fetch_max(f64::to_bits(v), Ordering::Relaxed);
+                    false
+                }
+                None => true,
             }
+        });
+
+        if aborted
+        {
+            return SearchResult::Aborted;
         }
 
-        state = set_in_packed_state(state, row, col, 0);
+        best_value_so_far = f64::from_bits(shared_best.load(Ordering::Relaxed));
+    }
 
-        if expected_value > best_value_so_far
+    cache.insert(key, best_value_so_far);
+    return SuccessfulSearch(best_value_so_far);
+}
+
+
+// expected value of revealing (row, col), only exact if it's above best_value_so_far.
+// None if the search has been aborted
+fn sh_square(
+    depth: usize,
+    mut state: u64,
+    row: usize,
+    col: usize,
+    acc: &[[[f64;4];5];5],
+    optimistic: f64, // no symbol is worth more
+    best_value_so_far: f64,
+    squares_by_depth: &mut Vec<Vec<(usize,usize)>>,
+    board_index: &BoardIndex,
+    board_sets: &mut Vec<Vec<u64>>,
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    cache: &TranspositionTable,
+    symmetries: &Symmetries,
+    scratch: &ScratchPool,
+    stats: &SearchStats,
+    cancel: &CancelToken,
+    mode: SearchMode,
+) -> Option<f64>
+{
+    let mut expected_value = 0.0;
+    let prob_not_bomb = acc[row][col][1] + acc[row][col][2] + acc[row][col][3];
+
+    // what each symbol leads to is worth at most the optimistic value of the boards it leaves.
+    // That only depends on the boards in Coins mode (the most coins of a board), so only Coins mode
+    // looks at the boards once more, and only if the cheap bound doesn't cut already.
+    // The children get their value, so it's not computed twice
+    let mut known_bounds = [None; 4];
+
+    if mode == SearchMode::Coins && prob_not_bomb * optimistic > best_value_so_far
+    {
+        let max_coins = board_index.max_coins_by_symbol(&board_sets[depth], row, col);
+        for symbol in 1..4
         {
-            best_value_so_far = expected_value;
+            known_bounds[symbol] = Some(max_coins[symbol] as f64);
         }
+    }
+
+    let child_bounds = known_bounds.map(|bound| bound.unwrap_or(optimistic));
+    let mut upper_bound_ev: f64 = (1..4).map(|symbol| acc[row][col][symbol] * child_bounds[symbol]).sum();
 
-        // no bomb, doesn't get any better than free information, this must be the best square
-        if acc[row][col][0] == 0.0
+    for symbol in 1..4
+    {
+        if acc[row][col][symbol] == 0.0
+        {
+            continue;
+        }
+
+        // cut if it can't be better than current best
+        if expected_value + upper_bound_ev <= best_value_so_far
         {
-            if mode != SearchMode::WinEight &&
-                mode != SearchMode::SurviveLevel &&
-                mode != SearchMode::SurviveEight
+            stats.count_cutoff(depth);
+            break;
+        }
+
+        state = set_in_packed_state(state, row, col, symbol);
+
+        if let SearchResult::SuccessfulSearch(r) = sh_exact(
+            depth + 1,
+            state,
+            (row, col, symbol),
+            known_bounds[symbol],
+            squares_by_depth,
+            board_index,
+            board_sets,
+            &sr, &sc, &br, &bc, level,
+            cache,
+            symmetries,
+            scratch,
+            stats,
+            &cancel,
+            mode,
+        ) {
+            expected_value += r * acc[row][col][symbol];
+            upper_bound_ev -= acc[row][col][symbol] * child_bounds[symbol];
+        } else {
+            return None;
+        }
+    }
+
+    Some(expected_value)
+}
+
+
+// Whether one of the squares searched before squares[i] can stand in for it: its symbol follows from
+// the symbol of that square in every board left, and it's a Voltorb wherever that square is one.
+// Flipping that square instead is never worse, it tells everything squares[i] would and squares[i]
+// can still be flipped safely whenever it would have been. Only in Coins, SurviveLevel and SurviveEight:
+// in WinEight winning sooner can be worth less, in WinChance it saved 0.05% of the nodes of the
+// benchmark and cost 6% more time. Squares the cheap bound cuts anyway aren't compared
+fn is_dominated(
+    squares: &[(usize, usize)],
+    i: usize,
+    acc: &[[[f64;4];5];5],
+    optimistic: f64,
+    best_value_so_far: f64,
+    board_index: &BoardIndex,
+    boards: &[u64],
+    mode: SearchMode,
+) -> bool
+{
+    let (row, col) = squares[i];
+    let prob_not_bomb = acc[row][col][1] + acc[row][col][2] + acc[row][col][3];
+
+    let pays = matches!(mode, SearchMode::Coins | SearchMode::SurviveLevel | SearchMode::SurviveEight);
+
+    if !pays || prob_not_bomb * optimistic <= best_value_so_far
+    {
+        return false;
+    }
+
+    // it can't be a Voltorb more often and the chance of each of its symbols is the sum of the
+    // chances of some symbols of the other square, checked first as it's cheap
+    squares[..i].iter().any(|&(r2, c2)| {
+        acc[r2][c2][0] <= acc[row][col][0] + 1e-12 &&
+            is_sum_of_chances(&acc[row][col], &acc[r2][c2]) &&
+            board_index.is_determined_by(boards, row * 5 + col, r2 * 5 + c2)
+    })
+}
+
+
+// every chance in probs is the sum of some chances in other_probs
+fn is_sum_of_chances(
+    probs: &[f64;4],
+    other_probs: &[f64;4],
+) -> bool
+{
+    probs.iter().filter(|&&p| p > 0.0).all(|&p| {
+        (1..16_usize).any(|subset| {
+            let sum: f64 = (0..4).filter(|&t| subset & (1 << t) != 0).map(|t| other_probs[t]).sum();
+            (sum - p).abs() < 1e-9
+        })
+    })
+}
+
+
+// The squares worth flipping in that state, most likely safe first (ties: most likely useful first).
+// 'Useless' squares only count in the modes that need a number of flips, of each row and column group
+// only the first square (see is_good_assignment) if symmetries has equal lines. Replaces what squares held
+pub fn candidate_squares(
+    state: u64,
+    acc: &[[[f64;4];5];5],
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    symmetries: &Symmetries,
+    mode: SearchMode,
+    squares: &mut Vec<(usize, usize)>,
+)
+{
+    squares.clear();
+
+    for row in 0..5
+    {
+        for col in 0..5
+        {
+            if get_from_packed_state(state, row, col) == 0 &&
+                (acc[row][col][2] > 0.0 || acc[row][col][3] > 0.0 ||
+                    mode == SearchMode::WinEight ||
+                    mode == SearchMode::SurviveEight ||
+                    mode == SearchMode::SurviveLevel) &&
+                (!symmetries.has_equal_lines() || is_good_assignment(state, &sr, &sc, &br, &bc, row, col))
             {
-                break;
-            }
-            else {
-                // but when trying to uncover at least a certain number of cards
-                // this might end the game too early, so don't skip here
+                squares.push((row, col));
             }
         }
     }
 
-    cache.insert(state, best_value_so_far);
-    return SuccessfulSearch(best_value_so_far);
+    squares.sort_unstable_by_key(
+        |&(row, col)| {
+            FloatOrd(1024.0 * acc[row][col][0] - acc[row][col][2] - acc[row][col][3])
+        }
+    );
+}
+
+
+// resigning keeps the coins in Coins mode, it's worth nothing in the others
+pub fn resign_value(state: u64, mode: SearchMode) -> f64
+{
+    if mode == SearchMode::Coins
+    {
+        coins_of_state(state) as f64
+    }
+    else {
+        0.0
+    }
+}
+
+
+// no state with these boards left is worth more: the most coins of a board in Coins mode, else 1
+pub fn optimistic_value(board_index: &BoardIndex, boards: &[u64], mode: SearchMode) -> f64
+{
+    if mode == SearchMode::Coins
+    {
+        board_index.max_coins(boards) as f64
+    }
+    else {
+        1.0
+    }
+}
+
+
+// no bomb, doesn't get any better than free information, this must be the best square
+pub fn is_best_square(
+    acc: &[[[f64;4];5];5],
+    row: usize,
+    col: usize,
+    mode: SearchMode,
+) -> bool
+{
+    // but when trying to uncover at least a certain number of cards
+    // this might end the game too early, so don't skip there
+    acc[row][col][0] == 0.0 &&
+        mode != SearchMode::WinEight &&
+        mode != SearchMode::SurviveLevel &&
+        mode != SearchMode::SurviveEight
 }
 
 
 // Is the current square the leftmost and topmost in it's row and column group?
-fn is_good_assignment(
+pub fn is_good_assignment(
     packed_state: u64,
     sr: &[usize; 5],
     sc: &[usize; 5],
@@ -703,7 +925,8 @@ fn try_swap_one_row_or_col(
     sc: &[usize; 5],
     br: &[usize; 5],
     bc: &[usize; 5],
-    cache_chances: &DashMap<u64,f64>
+    cache_chances: &TranspositionTable,
+    symmetries: &Symmetries,
 ) -> Option<f64>
 {
     // mutable copy of the state to permutate
@@ -795,11 +1018,11 @@ fn try_swap_one_row_or_col(
 
                     let key = packed_permutated_state;
 
-                    match cache_chances.get(&key)
+                    match cache_chances.get(&symmetries.canonical(key))
                     {
                         Some(r) =>
                             {
-                                return Some(*r)
+                                return Some(r)
                             },
                         None => (),
                     }
@@ -895,10 +1118,10 @@ fn try_swap_one_row_or_col(
                     let key = transpose_packed(packed_permutated_state);
                     // no need to transpose back :)
 
-                    match cache_chances.get(&key)
+                    match cache_chances.get(&symmetries.canonical(key))
                     {
                         Some(r) => {
-                            return Some(*r)
+                            return Some(r)
                         },
                         None => (),
                     }
@@ -995,8 +1218,73 @@ fn same_col_group(
 }
 
 
+// value of a state below the root if the game is over for that mode, None if it goes on
+// is_won tells whether all 2 and 3 have been found, only called when the mode cares
+pub fn terminal_value<F: Fn() -> bool>(
+    state: u64,
+    is_won: F,
+    level: usize,
+    mode: SearchMode,
+) -> Option<f64>
+{
+    // TODO Lost states are modeled implicitly by not adding anything
+    // But if I bomb after having uncovered x squares, haven't I still won?
+    // Is that handled?
+    // TODO in WinEight mode, the program recommended a 100% three ending with less than 8 squares
+    match mode
+    {
+        SearchMode::WinChance => {
+            if is_won()
+            {
+                return Some(1.0);
+            }
+        }
+
+        SearchMode::SurviveLevel => {
+            if count_assigned_packed(state) >= level
+            {
+                return Some(1.0);
+            }
+        }
+
+        SearchMode::WinEight => {
+            if is_won()
+            {
+                return if count_assigned_packed(state) >= 8
+                {
+                    Some(1.0)
+                } else {
+                    // winning too early isn't worth anything
+                    Some(0.0)
+                }
+            }
+        }
+
+        SearchMode::SurviveEight => {
+            if count_assigned_packed(state) >= 8
+            {
+                return Some(1.0);
+            }
+        }
+
+        SearchMode::SurviveNextMove => {
+            return Some(1.0); // We've survived the next move :O
+        }
+
+        SearchMode::Coins => {
+            if is_won()
+            {
+                return Some(coins_of_state(state) as f64);
+            }
+        }
+    }
+
+    None
+}
+
+
 // terminate if there's no 2 or 3 left (game over, you win)
-fn is_won_state(
+pub fn is_won_state(
     packed_state: u64,
     possible_boards: &Vec<u64>,
     indices: &Vec<usize>,
@@ -0,0 +1,271 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+// depths of the search, flips below the root
+pub const DEPTHS: usize = 26;
+// counting costs two clock reads and a few shared atomics per node, all threads adding to the same
+// lookup counters, so it's only on while looking at the statistics (benchmark_statistics,
+// SHOW_STATISTICS in the GUI). That includes the totals of the lookups and dominated squares
+pub const USE_DEPTH_STATISTICS: bool = false;
+
+
+// what happened at one depth of a search
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DepthStatistics
+{
+    pub nodes: usize, // expanded, so neither in the cache nor terminal nor found by a swap
+    pub cache_hits: usize,
+    pub swap_hits: usize, // found by try_swap_one_row_or_col
+    pub cutoffs: usize, // squares upper_bound_ev stopped before their last symbol
+    pub boards: usize, // boards left, summed over the expanded nodes
+    pub filter_seconds: f64, // narrowing down the boards, summed over all threads
+    pub accumulate_seconds: f64, // symbol probabilities of the expanded nodes, summed over all threads
+}
+
+impl DepthStatistics
+{
+    pub fn average_boards(&self) -> f64
+    {
+        if self.nodes == 0
+        {
+            0.0
+        }
+        else {
+            self.boards as f64 / self.nodes as f64
+        }
+    }
+}
+
+// one entry per depth, the root is depth 0 and only counted by the totals
+pub type SearchStatistics = [DepthStatistics; DEPTHS];
+
+
+// counters of one depth, on a cache line of their own so threads at other depths don't contend for it
+#[repr(align(128))]
+struct DepthCounters
+{
+    nodes: AtomicUsize,
+    cache_hits: AtomicUsize,
+    swap_hits: AtomicUsize,
+    cutoffs: AtomicUsize,
+    boards: AtomicUsize,
+    filter_nanos: AtomicU64,
+    accumulate_nanos: AtomicU64,
+}
+
+impl DepthCounters
+{
+    fn new() -> DepthCounters
+    {
+        DepthCounters {
+            nodes: AtomicUsize::new(0),
+            cache_hits: AtomicUsize::new(0),
+            swap_hits: AtomicUsize::new(0),
+            cutoffs: AtomicUsize::new(0),
+            boards: AtomicUsize::new(0),
+            filter_nanos: AtomicU64::new(0),
+            accumulate_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+
+// counters of a single search, shared by all of its threads
+pub struct SearchStats
+{
+    pub lookups: AtomicUsize,
+    pub hits: AtomicUsize,
+    pub merged_lookups: AtomicUsize, // the board-set key wasn't the key of the state itself
+    pub merged_hits: AtomicUsize, // hits only the board-set key could find
+    pub dominated: AtomicUsize, // squares not searched, a square searched before them can stand in for them
+    depths: Vec<DepthCounters>,
+}
+
+impl SearchStats
+{
+    pub fn new() -> SearchStats
+    {
+        SearchStats {
+            lookups: AtomicUsize::new(0),
+            hits: AtomicUsize::new(0),
+            merged_lookups: AtomicUsize::new(0),
+            merged_hits: AtomicUsize::new(0),
+            dominated: AtomicUsize::new(0),
+            depths: (0..DEPTHS).map(|_| DepthCounters::new()).collect(),
+        }
+    }
+
+    pub fn count_lookup(&self, depth: usize, hit: bool, merged: bool)
+    {
+        if !USE_DEPTH_STATISTICS
+        {
+            return;
+        }
+
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+
+        if hit
+        {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            self.depths[depth].cache_hits.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if merged
+        {
+            self.merged_lookups.fetch_add(1, Ordering::Relaxed);
+
+            if hit
+            {
+                self.merged_hits.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn count_dominated(&self)
+    {
+        if !USE_DEPTH_STATISTICS
+        {
+            return;
+        }
+
+        self.dominated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_swap_hit(&self, depth: usize)
+    {
+        if !USE_DEPTH_STATISTICS
+        {
+            return;
+        }
+
+        self.depths[depth].swap_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_cutoff(&self, depth: usize)
+    {
+        if !USE_DEPTH_STATISTICS
+        {
+            return;
+        }
+
+        self.depths[depth].cutoffs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // an expanded node with that many boards left
+    pub fn count_node(&self, depth: usize, boards: usize)
+    {
+        if !USE_DEPTH_STATISTICS
+        {
+            return;
+        }
+
+        self.depths[depth].nodes.fetch_add(1, Ordering::Relaxed);
+        self.depths[depth].boards.fetch_add(boards, Ordering::Relaxed);
+    }
+
+    // start of a timed step, None if the steps aren't timed
+    pub fn start_timer(&self) -> Option<Instant>
+    {
+        if USE_DEPTH_STATISTICS { Some(Instant::now()) } else { None }
+    }
+
+    // time since start spent narrowing down the boards
+    pub fn add_filter_time(&self, depth: usize, start: Option<Instant>)
+    {
+        if let Some(start) = start
+        {
+            self.depths[depth].filter_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    // time since start spent on the symbol probabilities
+    pub fn add_accumulate_time(&self, depth: usize, start: Option<Instant>)
+    {
+        if let Some(start) = start
+        {
+            self.depths[depth].accumulate_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn per_depth(&self) -> SearchStatistics
+    {
+        let mut statistics = [DepthStatistics::default(); DEPTHS];
+
+        for (depth, counters) in self.depths.iter().enumerate()
+        {
+            statistics[depth] = DepthStatistics {
+                nodes: counters.nodes.load(Ordering::Relaxed),
+                cache_hits: counters.cache_hits.load(Ordering::Relaxed),
+                swap_hits: counters.swap_hits.load(Ordering::Relaxed),
+                cutoffs: counters.cutoffs.load(Ordering::Relaxed),
+                boards: counters.boards.load(Ordering::Relaxed),
+                filter_seconds: counters.filter_nanos.load(Ordering::Relaxed) as f64 * 1e-9,
+                accumulate_seconds: counters.accumulate_nanos.load(Ordering::Relaxed) as f64 * 1e-9,
+            };
+        }
+
+        statistics
+    }
+
+    // nothing was counted without USE_DEPTH_STATISTICS
+    pub fn report(&self)
+    {
+        if !USE_DEPTH_STATISTICS
+        {
+            return;
+        }
+
+        let lookups = self.lookups.load(Ordering::Relaxed);
+        let hits = self.hits.load(Ordering::Relaxed);
+        let merged_hits = self.merged_hits.load(Ordering::Relaxed);
+
+        info!("Thread: {} cache lookups, {} hits ({:.1}%)",
+              lookups, hits, percentage(hits, lookups));
+
+        if self.merged_lookups.load(Ordering::Relaxed) > 0
+        {
+            info!("Thread: Board-set keys merged {} lookups into known states ({:.1}% of lookups, {:.1}% of hits)",
+                  merged_hits, percentage(merged_hits, lookups), percentage(merged_hits, hits));
+        }
+
+        let dominated = self.dominated.load(Ordering::Relaxed);
+        if dominated > 0
+        {
+            info!("Thread: Dominance pruning saved searching {} squares", dominated);
+        }
+    }
+}
+
+
+// one line per depth that got searched at all, with a header
+pub fn format_statistics(statistics: &SearchStatistics) -> String
+{
+    let mut table = format!("{:>5} {:>10} {:>10} {:>8} {:>9} {:>10} {:>9} {:>12}\n",
+                            "depth", "nodes", "hits", "swaps", "cutoffs", "boards", "filter s", "accumulate s");
+
+    for (depth, d) in statistics.iter().enumerate()
+    {
+        if d.nodes + d.cache_hits + d.swap_hits == 0
+        {
+            continue;
+        }
+
+        table.push_str(&format!("{:>5} {:>10} {:>10} {:>8} {:>9} {:>10.1} {:>9.3} {:>12.3}\n",
+                                depth, d.nodes, d.cache_hits, d.swap_hits, d.cutoffs,
+                                d.average_boards(), d.filter_seconds, d.accumulate_seconds));
+    }
+
+    table
+}
+
+
+fn percentage(part: usize, total: usize) -> f64
+{
+    if total == 0
+    {
+        0.0
+    }
+    else {
+        100.0 * part as f64 / total as f64
+    }
+}
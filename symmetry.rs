@@ -0,0 +1,194 @@
+use std::cmp::min;
+use crate::packed::{get_from_packed_state, set_in_packed_state};
+
+
+// row and column permutations (and transposing) which map every board fitting the constraints
+// onto another board fitting them. Level weights only depend on symbol counts and specials per line,
+// so equivalent states have the same value in every mode and can share one cache entry
+pub struct Symmetries
+{
+    row_classes: Vec<Vec<usize>>, // rows with the same sum and Voltorbs, only classes of 2 or more
+    col_permutations: Vec<Vec<u16>>, // for each column permutation: 10 bits of a row -> permuted 10 bits
+    transposition: Option<([usize;5], [usize;5])>, // row that column i becomes, column that row j becomes
+    trivial: bool, // nothing but the identity
+    equal_lines: bool, // lines with the same remaining sum, Voltorbs and face-down squares are interchangeable
+}
+
+impl Symmetries
+{
+    pub fn new(
+        sr: &[usize; 5],
+        sc: &[usize; 5],
+        br: &[usize; 5],
+        bc: &[usize; 5],
+    ) -> Symmetries
+    {
+        let mut row_classes: Vec<Vec<usize>> = Vec::new();
+        for r in 0..5
+        {
+            match row_classes.iter_mut().find(|class| (sr[class[0]], br[class[0]]) == (sr[r], br[r]))
+            {
+                Some(class) => class.push(r),
+                None => row_classes.push(vec![r]),
+            }
+        }
+        row_classes.retain(|class| class.len() > 1);
+
+        // column c of the permuted state is column perm[c] of the original one
+        let col_permutations: Vec<Vec<u16>> = permutations_of_five()
+            .into_iter()
+            .filter(|perm| (0..5).all(|c| (sc[perm[c]], bc[perm[c]]) == (sc[c], bc[c])))
+            .map(|perm| {
+                (0..1024_u64).map(|bits| {
+                    let mut permuted = 0;
+                    for c in 0..5
+                    {
+                        permuted |= ((bits >> ((4 - perm[c]) * 2)) & 0x3) << ((4 - c) * 2);
+                    }
+                    permuted as u16
+                }).collect()
+            })
+            .collect();
+
+        let transposition = match (line_matching(&sc, &bc, &sr, &br), line_matching(&sr, &br, &sc, &bc))
+        {
+            (Some(row_of_col), Some(col_of_row)) => Some((row_of_col, col_of_row)),
+            _ => None,
+        };
+
+        let trivial = row_classes.is_empty() && col_permutations.len() == 1 && transposition.is_none();
+
+        Symmetries {
+            row_classes,
+            col_permutations,
+            transposition,
+            trivial,
+            equal_lines: true,
+        }
+    }
+
+    // Only the identity. For board sets that aren't closed under the symmetries of the puzzle (a sample
+    // of the boards), there equal lines aren't interchangeable either
+    pub fn none() -> Symmetries
+    {
+        Symmetries {
+            row_classes: Vec::new(),
+            col_permutations: vec![(0..1024).collect()],
+            transposition: None,
+            trivial: true,
+            equal_lines: false,
+        }
+    }
+
+    // may a search flip only one square of equal lines (see is_good_assignment) and look up
+    // the states with two of them swapped (see try_swap_one_row_or_col)?
+    pub fn has_equal_lines(&self) -> bool
+    {
+        self.equal_lines
+    }
+
+    // smallest packed state equivalent to this one
+    pub fn canonical(&self, state: u64) -> u64
+    {
+        if self.trivial
+        {
+            return state;
+        }
+
+        let mut canonical = self.canonical_without_transposing(state);
+
+        if let Some((row_of_col, col_of_row)) = self.transposition
+        {
+            let mut transposed = 0;
+            for r in 0..5
+            {
+                for c in 0..5
+                {
+                    let symbol = get_from_packed_state(state, r, c);
+                    transposed = set_in_packed_state(transposed, row_of_col[c], col_of_row[r], symbol);
+                }
+            }
+
+            canonical = min(canonical, self.canonical_without_transposing(transposed));
+        }
+
+        canonical
+    }
+
+    // row 0 holds the most significant bits, so for each column permutation
+    // the smallest rows of a class go to its topmost positions
+    fn canonical_without_transposing(&self, state: u64) -> u64
+    {
+        let mut canonical = u64::MAX;
+
+        for permutation in &self.col_permutations
+        {
+            let mut rows = [0_u64; 5];
+            for r in 0..5
+            {
+                rows[r] = permutation[((state >> ((4 - r) * 10)) & 0x3FF) as usize] as u64;
+            }
+
+            for class in &self.row_classes
+            {
+                let mut values = [0_u64; 5];
+                for (i, &r) in class.iter().enumerate()
+                {
+                    values[i] = rows[r];
+                }
+                values[..class.len()].sort_unstable();
+                for (i, &r) in class.iter().enumerate()
+                {
+                    rows[r] = values[i];
+                }
+            }
+
+            canonical = min(canonical, rows.iter().fold(0, |packed, &row| (packed << 10) | row));
+        }
+
+        canonical
+    }
+}
+
+
+// for each line of the first kind a distinct line of the second kind with the same sum and Voltorbs
+fn line_matching(
+    sums_from: &[usize; 5],
+    bombs_from: &[usize; 5],
+    sums_to: &[usize; 5],
+    bombs_to: &[usize; 5],
+) -> Option<[usize; 5]>
+{
+    let mut matching = [0; 5];
+    let mut used = [false; 5];
+
+    for i in 0..5
+    {
+        let j = (0..5).find(|&j| !used[j] && (sums_to[j], bombs_to[j]) == (sums_from[i], bombs_from[i]))?;
+        used[j] = true;
+        matching[i] = j;
+    }
+
+    Some(matching)
+}
+
+
+fn permutations_of_five() -> Vec<[usize; 5]>
+{
+    let mut permutations = Vec::with_capacity(120);
+    for a in 0..5
+    {
+        for b in (0..5).filter(|&b| b != a)
+        {
+            for c in (0..5).filter(|&c| c != a && c != b)
+            {
+                for d in (0..5).filter(|&d| d != a && d != b && d != c)
+                {
+                    let e = 10 - a - b - c - d;
+                    permutations.push([a, b, c, d, e]);
+                }
+            }
+        }
+    }
+    permutations
+}
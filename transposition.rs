@@ -0,0 +1,433 @@
+use std::sync::Mutex;
+use std::cmp::min;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crate::math::count_assigned_packed;
+use crate::gui::SearchMode;
+
+
+// which entry of a full bucket gets thrown out for a new one
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReplacementPolicy
+{
+    DepthPreferred, // keep the states with more face-down squares, their subtrees are the expensive ones
+    AlwaysReplace, // keep the most recent states
+    TwoTier, // half of each bucket is depth-preferred, the other half always-replace
+}
+
+// how values are stored
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValuePrecision
+{
+    F64, // exact, 16 bytes per entry
+    F32, // rounded to f32, 8 bytes per entry. Not for coins, those need the digits
+}
+
+// entries of a bucket share a cache line (with F64 entries)
+const SLOTS: usize = 4;
+
+// packed states only use 50 bits
+const STATE_BITS: u32 = 50;
+const STATE_MASK: u64 = (1 << STATE_BITS) - 1;
+
+// set in every used entry, an all zero entry is empty
+const OCCUPIED: u64 = 1 << 63;
+
+// odd, so multiplying with it is a bijection on 50 bit numbers
+const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+
+// MIX * MIX_INVERSE = 1 modulo 2^64 (Newton's method, every step doubles the correct bits)
+const MIX_INVERSE: u64 = {
+    let mut inverse = MIX;
+    let mut i = 0;
+    while i < 5
+    {
+        inverse = inverse.wrapping_mul(2_u64.wrapping_sub(MIX.wrapping_mul(inverse)));
+        i += 1;
+    }
+    inverse
+};
+
+// F32 entries keep 31 bits of the mixed state, the bucket index has to provide the rest
+const MIN_BUCKET_BITS_F32: u32 = STATE_BITS - 31;
+
+
+// lock-free cache of values with a fixed memory footprint (open addressing, buckets of SLOTS entries).
+// The state is mixed bijectively, its top bits pick the bucket.
+// F64 entries are two words (state ^ value, value), a reader only accepts them if they xor to a state,
+// so a half written entry is practically never mistaken for a value.
+// F32 entries are a single word: occupied bit, the mixed state without the bucket bits and the value.
+// Entries always hold their full state, evicted values are simply recomputed.
+// The whole table belongs to a single puzzle and mode, see bind
+pub struct TranspositionTable
+{
+    words: Vec<AtomicU64>,
+    bucket_bits: u32,
+    policy: ReplacementPolicy,
+    precision: ValuePrecision,
+    len: AtomicUsize, // approximate when threads race for the same slot
+    fingerprint: Mutex<Option<u64>>, // puzzle and mode of the values, None if not bound yet
+}
+
+impl TranspositionTable
+{
+    pub fn new(megabytes: usize, policy: ReplacementPolicy, precision: ValuePrecision) -> TranspositionTable
+    {
+        let bucket_bytes = SLOTS * words_per_entry(precision) * 8;
+
+        // largest power of two that fits
+        let mut bucket_bits = 0;
+        while bucket_bits < STATE_BITS && (bucket_bytes << (bucket_bits + 1)) <= (megabytes << 20)
+        {
+            bucket_bits += 1;
+        }
+
+        if precision == ValuePrecision::F32 && bucket_bits < MIN_BUCKET_BITS_F32
+        {
+            warn!("{} MB are too few for f32 entries, using {} MB",
+                  megabytes, (bucket_bytes << MIN_BUCKET_BITS_F32) >> 20);
+            bucket_bits = MIN_BUCKET_BITS_F32;
+        }
+
+        let word_count = (SLOTS << bucket_bits) * words_per_entry(precision);
+
+        info!("Using {} MB for the transposition table ({} entries, {:?}, {:?})",
+              (word_count * 8) >> 20, SLOTS << bucket_bits, policy, precision);
+
+        TranspositionTable {
+            words: (0..word_count).map(|_| AtomicU64::new(0)).collect(),
+            bucket_bits,
+            policy,
+            precision,
+            len: AtomicUsize::new(0),
+            fingerprint: Mutex::new(None),
+        }
+    }
+
+    // the values belong to the puzzle and mode with that fingerprint from now on,
+    // if the table held values of another one they are thrown away. Returns whether it had to clear
+    pub fn bind(&self, fingerprint: u64) -> bool
+    {
+        let mut bound = self.fingerprint.lock().expect("Failed to lock cache fingerprint");
+
+        if *bound == Some(fingerprint)
+        {
+            return false;
+        }
+
+        *bound = Some(fingerprint);
+
+        if self.len() == 0
+        {
+            return false;
+        }
+
+        info!("Cache holds values of another puzzle or mode, clearing it");
+        self.clear();
+        true
+    }
+
+    pub fn fingerprint(&self) -> Option<u64>
+    {
+        *self.fingerprint.lock().expect("Failed to lock cache fingerprint")
+    }
+
+    pub fn get(&self, state: &u64) -> Option<f64>
+    {
+        let bucket = self.bucket_of(*state);
+
+        for slot in 0..SLOTS
+        {
+            if let Some((s, value)) = self.read(bucket, slot)
+            {
+                if s == *state
+                {
+                    return Some(value);
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn insert(&self, state: u64, value: f64)
+    {
+        let bucket = self.bucket_of(state);
+        let mut entries = [None; SLOTS];
+
+        // already there or a free slot
+        for slot in 0..SLOTS
+        {
+            entries[slot] = self.read(bucket, slot);
+
+            if let Some((s, _)) = entries[slot]
+            {
+                if s == state
+                {
+                    self.write(bucket, slot, state, value);
+                    return;
+                }
+            }
+        }
+
+        if let Some(slot) = entries.iter().position(|e| e.is_none())
+        {
+            self.write(bucket, slot, state, value);
+            self.len.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        // full, something has to go
+        let spread = (mix(state) as usize) & (SLOTS / 2 - 1);
+
+        match self.policy
+        {
+            ReplacementPolicy::DepthPreferred => {
+                if let Some(slot) = weakest_if_weaker(&entries, 0..SLOTS, state)
+                {
+                    self.write(bucket, slot, state, value);
+                }
+            }
+
+            ReplacementPolicy::AlwaysReplace => {
+                self.write(bucket, (mix(state) as usize) & (SLOTS - 1), state, value);
+            }
+
+            // first half depth-preferred, the second half takes whatever comes
+            ReplacementPolicy::TwoTier => {
+                match weakest_if_weaker(&entries, 0..SLOTS / 2, state)
+                {
+                    Some(slot) => self.write(bucket, slot, state, value),
+                    None => self.write(bucket, SLOTS / 2 + spread, state, value),
+                }
+            }
+        }
+    }
+
+    pub fn clear(&self)
+    {
+        for word in &self.words
+        {
+            word.store(0, Ordering::Relaxed);
+        }
+        self.len.store(0, Ordering::Relaxed);
+    }
+
+    // number of stored entries
+    pub fn len(&self) -> usize
+    {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn capacity(&self) -> usize
+    {
+        SLOTS << self.bucket_bits
+    }
+
+    pub fn memory_bytes(&self) -> usize
+    {
+        self.words.len() * 8
+    }
+
+    pub fn precision(&self) -> ValuePrecision
+    {
+        self.precision
+    }
+
+    // throws out every entry whose state doesn't pass keep, returns how many
+    pub fn retain<F: Fn(u64) -> bool>(&self, keep: F) -> usize
+    {
+        let mut removed = 0;
+
+        for bucket in 0..(1 << self.bucket_bits)
+        {
+            for slot in 0..SLOTS
+            {
+                if let Some((state, _)) = self.read(bucket, slot)
+                {
+                    if !keep(state)
+                    {
+                        let i = (bucket * SLOTS + slot) * words_per_entry(self.precision);
+                        for word in &self.words[i..i + words_per_entry(self.precision)]
+                        {
+                            word.store(0, Ordering::Relaxed);
+                        }
+                        removed += 1;
+                    }
+                }
+            }
+        }
+
+        self.len.fetch_sub(min(removed, self.len()), Ordering::Relaxed);
+        removed
+    }
+
+    pub fn for_each<F: FnMut(u64, f64)>(&self, mut f: F)
+    {
+        for bucket in 0..(1 << self.bucket_bits)
+        {
+            for slot in 0..SLOTS
+            {
+                if let Some((state, value)) = self.read(bucket, slot)
+                {
+                    f(state, value);
+                }
+            }
+        }
+    }
+
+    fn bucket_of(&self, state: u64) -> usize
+    {
+        (mix(state) >> (STATE_BITS - self.bucket_bits)) as usize
+    }
+
+    fn read(&self, bucket: usize, slot: usize) -> Option<(u64, f64)>
+    {
+        let i = (bucket * SLOTS + slot) * words_per_entry(self.precision);
+
+        match self.precision
+        {
+            ValuePrecision::F64 => {
+                let value_bits = self.words[i + 1].load(Ordering::Relaxed);
+                let key = self.words[i].load(Ordering::Relaxed) ^ value_bits;
+
+                // empty or torn
+                if key & OCCUPIED == 0 || key & !OCCUPIED & !STATE_MASK != 0
+                {
+                    return None;
+                }
+
+                Some((key & STATE_MASK, f64::from_bits(value_bits)))
+            }
+
+            ValuePrecision::F32 => {
+                let word = self.words[i].load(Ordering::Relaxed);
+                if word & OCCUPIED == 0
+                {
+                    return None;
+                }
+
+                let rest = (word & !OCCUPIED) >> 32;
+                let mixed = ((bucket as u64) << (STATE_BITS - self.bucket_bits)) | rest;
+
+                Some((unmix(mixed), f32::from_bits(word as u32) as f64))
+            }
+        }
+    }
+
+    fn write(&self, bucket: usize, slot: usize, state: u64, value: f64)
+    {
+        let i = (bucket * SLOTS + slot) * words_per_entry(self.precision);
+
+        match self.precision
+        {
+            ValuePrecision::F64 => {
+                let value_bits = value.to_bits();
+                self.words[i + 1].store(value_bits, Ordering::Relaxed);
+                self.words[i].store((state | OCCUPIED) ^ value_bits, Ordering::Relaxed);
+            }
+
+            ValuePrecision::F32 => {
+                let rest = mix(state) & ((1 << (STATE_BITS - self.bucket_bits)) - 1);
+                self.words[i].store(OCCUPIED | (rest << 32) | (value as f32).to_bits() as u64, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+
+// identifies the puzzle (constraints and level) and mode a value belongs to,
+// stable across runs and program versions (FNV-1a)
+pub fn fingerprint(
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    mode: SearchMode,
+) -> u64
+{
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+
+    let mut feed = |x: u64| {
+        for byte in x.to_le_bytes().iter()
+        {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    };
+
+    for line in [sr, sc, br, bc].iter()
+    {
+        for &x in line.iter()
+        {
+            feed(x as u64);
+        }
+    }
+    feed(level as u64);
+    feed(mode_id(mode));
+
+    hash
+}
+
+
+// never reorder these, they end up on disk
+pub fn mode_id(mode: SearchMode) -> u64
+{
+    match mode
+    {
+        SearchMode::WinChance => 0,
+        SearchMode::WinEight => 1,
+        SearchMode::SurviveNextMove => 2,
+        SearchMode::SurviveLevel => 3,
+        SearchMode::SurviveEight => 4,
+        SearchMode::Coins => 5,
+    }
+}
+
+
+fn words_per_entry(precision: ValuePrecision) -> usize
+{
+    match precision
+    {
+        ValuePrecision::F64 => 2,
+        ValuePrecision::F32 => 1,
+    }
+}
+
+
+fn mix(state: u64) -> u64
+{
+    state.wrapping_mul(MIX) & STATE_MASK
+}
+
+
+fn unmix(mixed: u64) -> u64
+{
+    mixed.wrapping_mul(MIX_INVERSE) & STATE_MASK
+}
+
+
+// slot with the fewest face-down squares among slots, if the new state has at least as many
+fn weakest_if_weaker(
+    entries: &[Option<(u64, f64)>; SLOTS],
+    slots: std::ops::Range<usize>,
+    state: u64,
+) -> Option<usize>
+{
+    let weakest = slots.min_by_key(|&slot| priority(entries[slot].expect("Bucket is full").0))?;
+
+    if priority(state) >= priority(entries[weakest].expect("Bucket is full").0)
+    {
+        Some(weakest)
+    }
+    else {
+        None
+    }
+}
+
+
+// face-down squares, more of them means a bigger subtree behind that value
+fn priority(state: u64) -> usize
+{
+    25 - count_assigned_packed(state)
+}
@@ -0,0 +1,122 @@
+use crossbeam_channel::unbounded;
+use crate::search::{compute_win_chance_exact, SearchResult};
+use crate::cancel::CancelToken;
+use crate::gui::{ReportMessage, SearchMode};
+use crate::parsing::examples_357;
+use crate::transposition::{TranspositionTable, ReplacementPolicy, ValuePrecision, fingerprint};
+
+// One cache is shared by all puzzles and modes without ever clearing it by hand,
+// the results have to be the same as with a fresh cache for every search.
+// Panics if a value of another puzzle or mode leaked into a search
+
+pub fn validate_cache()
+{
+    let threads = num_cpus::get();
+    let modes = [
+        SearchMode::SurviveNextMove,
+        SearchMode::WinChance,
+        SearchMode::WinEight,
+        SearchMode::SurviveLevel,
+        SearchMode::SurviveEight,
+        SearchMode::Coins,
+    ];
+    let puzzles: Vec<_> = examples_357().into_iter().take(10).collect();
+
+    // a bound table doesn't answer for another puzzle or mode
+    let (_, sr, sc, br, bc, level, _) = puzzles[0];
+    let table = TranspositionTable::new(16, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+    table.bind(fingerprint(&sr, &sc, &br, &bc, level, SearchMode::WinChance));
+    table.insert(0, 0.5);
+    table.bind(fingerprint(&sr, &sc, &br, &bc, level, SearchMode::Coins));
+    assert_eq!(table.get(&0), None, "Value of another mode survived binding the cache");
+
+    // reference values, fresh cache each time
+    let mut expected = Vec::new();
+    for &(_, sr, sc, br, bc, level, _) in &puzzles
+    {
+        for &mode in &modes
+        {
+            let cache = TranspositionTable::new(64, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+            expected.push(solve(&sr, &sc, &br, &bc, level, mode, &cache, threads));
+        }
+    }
+
+    // same searches with one shared cache, twice so that every puzzle and mode
+    // also comes back to a cache full of values of the others
+    let shared = TranspositionTable::new(256, ReplacementPolicy::TwoTier, ValuePrecision::F64);
+    for round in 0..2
+    {
+        let mut i = 0;
+        for &(nr, sr, sc, br, bc, level, _) in &puzzles
+        {
+            for &mode in &modes
+            {
+                let (value, squares) = solve(&sr, &sc, &br, &bc, level, mode, &shared, threads);
+                let (expected_value, expected_squares) = &expected[i];
+
+                if !same(value, *expected_value) || squares.len() != expected_squares.len()
+                    || squares.iter().zip(expected_squares).any(|(a, b)| (a.0, a.1) != (b.0, b.1) || !same(Some(a.2), Some(b.2)))
+                {
+                    panic!("Shared cache changed the result of puzzle {} in {:?} (round {}): {:?} instead of {:?}",
+                           nr, mode, round, value, expected_value);
+                }
+
+                i += 1;
+            }
+        }
+
+        println!("Round {}: {} searches with a shared cache match", round, i);
+    }
+}
+
+
+// value of the position and of every square the search reported
+fn solve(
+    sr: &[usize; 5],
+    sc: &[usize; 5],
+    br: &[usize; 5],
+    bc: &[usize; 5],
+    level: usize,
+    mode: SearchMode,
+    cache: &TranspositionTable,
+    threads: usize,
+) -> (Option<f64>, Vec<(usize, usize, f64)>)
+{
+    let cancel = CancelToken::new();
+    let (result_reporting_sender, result_reporting_receiver) = unbounded();
+
+    let search_result = compute_win_chance_exact(
+        0, &sr, &sc, &br, &bc, level,
+        &cache,
+        &cancel,
+        &result_reporting_sender,
+        mode, threads);
+
+    let mut squares: Vec<_> = result_reporting_receiver
+        .try_iter()
+        .filter_map(|msg| match msg
+        {
+            ReportMessage::SquareWinProb(row, col, value) => Some((row, col, value)),
+            _ => None,
+        })
+        .collect();
+    squares.sort_by_key(|&(row, col, _)| (row, col));
+
+    match search_result
+    {
+        SearchResult::SuccessfulSearchWithInfo(value, _, _) => (Some(value), squares),
+        _ => (None, squares),
+    }
+}
+
+
+// values reached through a swapped row or column may differ in the last bits
+fn same(a: Option<f64>, b: Option<f64>) -> bool
+{
+    match (a, b)
+    {
+        (Some(a), Some(b)) => (a - b).abs() < 1e-9,
+        (None, None) => true,
+        _ => false,
+    }
+}
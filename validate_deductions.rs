@@ -0,0 +1,189 @@
+use crate::csp_constraints::find_possible_boards;
+use crate::deductions::{find_deductions_with_boards, describe_deduction, Certainty, Deduction};
+use crate::human_rules::{explain_forced_squares, describe_forced_square, ForcedSquare};
+use crate::packed::{get_from_packed_state, set_in_packed_state};
+use crate::parsing::examples_357;
+
+// Every square find_deductions reports has to hold in every possible board. Checked on the roots
+// of examples.txt and on the states reached by flipping their deduced safe squares one at a time.
+// Panics if a deduction is wrong, prints the deductions of the first puzzle as an example
+
+pub fn validate_deductions()
+{
+    let mut states = 0;
+    let mut deductions = 0;
+
+    for_each_state(|nr, _, _, _, _, _, possible_boards, found| {
+        for deduction in found
+        {
+            check_deduction(nr, deduction, possible_boards);
+        }
+
+        if nr == 1
+        {
+            for deduction in found
+            {
+                println!("Puzzle {}: {}", nr, describe_deduction(deduction));
+            }
+        }
+
+        states += 1;
+        deductions += found.len();
+    });
+
+    println!("{} deductions in {} states agree with every possible board", deductions, states);
+}
+
+
+// Every square the human rules force has to agree with find_deductions for that square and with
+// every possible board, and each step of its proof may only rely on steps before it.
+// Same states as validate_deductions, panics on the first mismatch, prints the proofs of the first puzzle
+
+pub fn validate_human_rules()
+{
+    let mut states = 0;
+    let mut forced_squares = 0;
+
+    for_each_state(|nr, sr, sc, br, bc, state, possible_boards, found| {
+        let forced = explain_forced_squares(state, sr, sc, br, bc)
+            .expect("The rules can't contradict a possible puzzle");
+
+        for square in &forced
+        {
+            check_forced_square(nr, square, found, possible_boards);
+
+            if nr == 1
+            {
+                println!("Puzzle {}:\n{}", nr, describe_forced_square(square).join("\n"));
+            }
+        }
+
+        states += 1;
+        forced_squares += forced.len();
+    });
+
+    println!("{} forced squares in {} states agree with the deductions and have ordered proofs",
+             forced_squares, states);
+}
+
+
+// calls visit with every state reached in examples.txt by flipping the deduced safe squares of the hidden
+// board one at a time, starting at the root, along with its constraints, possible boards and deductions
+fn for_each_state<F>(mut visit: F)
+    where F: FnMut(usize, &[usize; 5], &[usize; 5], &[usize; 5], &[usize; 5], u64, &Vec<u64>, &Vec<Deduction>)
+{
+    for (nr, sr, sc, br, bc, level, board) in examples_357()
+    {
+        let mut state = 0;
+
+        loop
+        {
+            let (possible_boards, _, _) = find_possible_boards(state, &sr, &sc, &br, &bc, level);
+            let found = find_deductions_with_boards(state, &possible_boards, &sr, &sc, &br, &bc)
+                .expect("The hidden board is always possible");
+
+            visit(nr, &sr, &sc, &br, &bc, state, &possible_boards, &found);
+
+            // flip the next square that's known to be safe, until there's none left
+            let next = found
+                .iter()
+                .find(|d| d.certainty != Certainty::Voltorb && d.certainty != Certainty::Useless);
+
+            match next
+            {
+                Some(d) => state = set_in_packed_state(state, d.row, d.col, get_from_packed_state(board, d.row, d.col)),
+                None => break,
+            }
+        }
+    }
+}
+
+
+fn check_deduction(
+    nr: usize,
+    deduction: &Deduction,
+    possible_boards: &Vec<u64>,
+)
+{
+    for &board in possible_boards
+    {
+        let symbol = get_from_packed_state(board, deduction.row, deduction.col);
+
+        if !certainty_holds(deduction.certainty, symbol)
+        {
+            panic!("Puzzle {}: {} but a possible board has a {} there",
+                   nr, describe_deduction(deduction), symbol);
+        }
+    }
+}
+
+
+fn check_forced_square(
+    nr: usize,
+    forced: &ForcedSquare,
+    deductions: &Vec<Deduction>,
+    possible_boards: &Vec<u64>,
+)
+{
+    let proof = describe_forced_square(forced).join("\n");
+
+    // the rules never know more than all the boards together
+    let deduction = deductions
+        .iter()
+        .find(|d| d.row == forced.row && d.col == forced.col)
+        .unwrap_or_else(|| panic!("Puzzle {}: no deduction for\n{}", nr, proof));
+
+    let safe = matches!(deduction.certainty, Certainty::Safe | Certainty::One | Certainty::Two | Certainty::Three);
+    if forced.candidates & 1 == 0 && !safe
+    {
+        panic!("Puzzle {}: the rules prove it's safe, find_deductions says {}\n{}",
+               nr, describe_deduction(deduction), proof);
+    }
+
+    if forced.candidates.count_ones() == 1
+    {
+        let symbol = forced.candidates.trailing_zeros() as usize;
+        if !matches!((deduction.certainty, symbol),
+            (Certainty::Voltorb, 0) | (Certainty::One, 1) | (Certainty::Two, 2) | (Certainty::Three, 3))
+        {
+            panic!("Puzzle {}: the rules prove a {}, find_deductions says {}\n{}",
+                   nr, symbol, describe_deduction(deduction), proof);
+        }
+    }
+
+    for &board in possible_boards
+    {
+        if forced.candidates & (1 << get_from_packed_state(board, forced.row, forced.col)) == 0
+        {
+            panic!("Puzzle {}: a possible board contradicts\n{}", nr, proof);
+        }
+    }
+
+    if forced.proof.is_empty()
+    {
+        panic!("Puzzle {}: no proof for\n{}", nr, proof);
+    }
+
+    for (i, step) in forced.proof.iter().enumerate()
+    {
+        if step.premises.iter().any(|&p| p >= i)
+        {
+            panic!("Puzzle {}: step {} relies on a later step\n{}", nr, i + 1, proof);
+        }
+    }
+}
+
+
+// symbol is possible for a square with that certainty
+fn certainty_holds(certainty: Certainty, symbol: usize) -> bool
+{
+    match certainty
+    {
+        Certainty::Voltorb => symbol == 0,
+        Certainty::One => symbol == 1,
+        Certainty::Two => symbol == 2,
+        Certainty::Three => symbol == 3,
+        Certainty::Safe => symbol != 0,
+        Certainty::Useless => symbol <= 1,
+    }
+}